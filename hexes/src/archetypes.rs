@@ -0,0 +1,98 @@
+use std::{fs, path::Path};
+
+use crate::{
+    data::{self, DataError, Fields},
+    map::{Movement, MovementProfile},
+};
+
+/// Built in copy of the enemy file, used when it can't be read
+const BUILTIN_ENEMIES: &str = include_str!("../../data/enemies.txt");
+
+/// A kind of enemy waves can spawn
+#[derive(Clone, Debug, PartialEq)]
+pub struct Archetype {
+    pub name: String,
+    /// Alias of the texture agents are drawn with
+    pub sprite: String,
+    pub health: u32,
+    /// Hexes moved per second
+    pub speed: f32,
+    /// Damage dealt to the base on reaching it
+    pub damage: u32,
+    pub movement: Movement,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Archetypes {
+    pub list: Vec<Archetype>,
+}
+
+impl Archetypes {
+    pub fn builtin() -> Archetypes {
+        Archetypes::from_text(BUILTIN_ENEMIES).unwrap()
+    }
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Archetypes, DataError> {
+        Archetypes::from_text(&fs::read_to_string(path)?)
+    }
+
+    /// Parses the format described at the top of `data/enemies.txt`
+    pub fn from_text(text: &str) -> Result<Archetypes, DataError> {
+        let mut list = vec![];
+
+        for (line, contents) in data::lines(text) {
+            let mut words = contents.split_whitespace();
+            if words.next() != Some("enemy") {
+                return Err(DataError::Malformed { line });
+            }
+
+            let name = words.next().ok_or(DataError::Malformed { line })?;
+            let fields = Fields::parse(words, line)?;
+
+            let movement = if fields.flag("flies")? {
+                Movement::Flying
+            } else {
+                Movement::Ground(MovementProfile {
+                    max_climb: fields.value("climb")?,
+                    crosses_bricks: fields.flag("bricks")?,
+                })
+            };
+
+            list.push(Archetype {
+                name: name.to_string(),
+                sprite: fields.get("sprite")?.to_string(),
+                health: fields.value("health")?,
+                speed: fields.value("speed")?,
+                damage: fields.value("damage")?,
+                movement,
+            });
+        }
+
+        if list.is_empty() {
+            return Err(DataError::NoEnemies);
+        }
+
+        Ok(Archetypes { list })
+    }
+
+    /// Index of the archetype called `name`
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.list
+            .iter()
+            .position(|archetype| archetype.name == name)
+    }
+
+    /// Every distinct movement profile among the archetypes that walk
+    pub fn profiles(&self) -> Vec<MovementProfile> {
+        let mut profiles = vec![];
+        for archetype in self.list.iter() {
+            if let Movement::Ground(profile) = archetype.movement {
+                if !profiles.contains(&profile) {
+                    profiles.push(profile);
+                }
+            }
+        }
+
+        profiles
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use vermarine_lib::hexmap::{Axial, CHUNK_HEIGHT, CHUNK_WIDTH};
+
+use crate::{
+    consts::*,
+    generation::NoiseGenerator,
+    map::{chunk_tile_position, FlowKey, Map, MovementProfile},
+};
+
+const BENCH_CHUNKS: usize = 16;
+const BENCH_EDITS: u32 = 200;
+
+/// Run with `--bench-flow`, times rebuilding the whole flow field against repairing it after
+/// random single tile edits, and checks both agree on every tile of the map.
+pub fn flow_field() {
+    let create_map = || {
+        let mut map = Map::with_size(
+            &mut NoiseGenerator::new(MAP_SEED),
+            BENCH_CHUNKS,
+            BENCH_CHUNKS,
+        );
+
+        let centre = (BENCH_CHUNKS * CHUNK_WIDTH / 2) as i32 - CHUNK_WIDTH as i32;
+        let goals = vec![
+            Axial::new(centre, centre).to_hex(),
+            Axial::new(centre + 1, centre).to_hex(),
+        ];
+        for &goal in goals.iter() {
+            map.flatten_tile(goal, 1);
+        }
+        let goals = map.set_goals(DEFAULT_GOALS, goals);
+        map.require_flow(FlowKey {
+            goals,
+            profile: MovementProfile::default(),
+        });
+
+        map
+    };
+
+    let mut rebuilt = create_map();
+    let mut repaired = create_map();
+    // Both maps registered the same goal set first, so it has the same id in both
+    let key = FlowKey {
+        goals: rebuilt.goal_set(DEFAULT_GOALS).unwrap(),
+        profile: MovementProfile::default(),
+    };
+
+    let mut rand = StdRng::seed_from_u64(MAP_SEED);
+    let mut rebuild_time = Duration::default();
+    let mut repair_time = Duration::default();
+
+    for _ in 0..BENCH_EDITS {
+        let (chunk_q, chunk_r) = rebuilt.chunks[rand.gen_range(0, rebuilt.chunks.len())];
+        let index = rand.gen_range(0, CHUNK_WIDTH * CHUNK_HEIGHT);
+        let hex = chunk_tile_position(chunk_q, chunk_r, index).to_hex();
+
+        for map in [&mut rebuilt, &mut repaired].iter_mut() {
+            let tile = map.terrain.get_tile_mut(hex).unwrap();
+            if tile.wall_height < MAX_BRICK_HEIGHT {
+                tile.wall_height += 1;
+            } else {
+                tile.wall_height = tile.ground_height;
+            }
+        }
+
+        let start = Instant::now();
+        rebuilt.refresh_dijkstra();
+        rebuild_time += start.elapsed();
+
+        let start = Instant::now();
+        repaired.repair_dijkstra(hex);
+        repair_time += start.elapsed();
+
+        for &(chunk_q, chunk_r) in rebuilt.chunks.iter() {
+            for index in 0..CHUNK_WIDTH * CHUNK_HEIGHT {
+                let hex = chunk_tile_position(chunk_q, chunk_r, index).to_hex();
+                assert_eq!(
+                    rebuilt.flow(hex, key),
+                    repaired.flow(hex, key),
+                    "repaired flow field differs at {:?}",
+                    hex.to_axial()
+                );
+            }
+        }
+    }
+
+    println!(
+        "{} edits on a {}x{} chunk map",
+        BENCH_EDITS, BENCH_CHUNKS, BENCH_CHUNKS
+    );
+    println!("full rebuild: {:?} per edit", rebuild_time / BENCH_EDITS);
+    println!("repair:       {:?} per edit", repair_time / BENCH_EDITS);
+}
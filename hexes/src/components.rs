@@ -1,33 +1,354 @@
-use vermarine_lib::hexmap::*;
+use std::collections::HashMap;
+
+use vermarine_lib::{
+    hexmap::*,
+    rendering::{draw_buffer::DrawCommand, Drawables, Sprite},
+    shipyard::EntityId,
+};
+
+use crate::{
+    consts::*,
+    map::{FlowKey, GoalSetId, HexTileData, Movement},
+};
 
 #[derive(Copy, Clone, Debug)]
 pub struct Transform {
+    /// Tile the entity is standing on, or is leaving while moving
     pub position: Axial,
+    /// Tile the entity is moving onto
+    pub next: Option<Axial>,
+    /// How far along the step from `position` to `next` the entity is, from 0 to 1
+    pub progress: f32,
+    /// Continuous position and height at the end of the previous tick, rendering
+    /// interpolates from here
+    pub previous: FractionalAxial,
+    pub previous_height: f32,
+    /// Height in levels of entities flying above the terrain instead of standing on it
+    pub altitude: Option<f32>,
 }
 
 impl Transform {
-    pub fn new(position: Axial) -> Self {
-        Transform { position }
+    pub fn new(position: Axial, height: f32) -> Self {
+        Transform {
+            position,
+            next: None,
+            progress: 0.,
+            previous: FractionalAxial {
+                q: position.q as f32,
+                r: position.r as f32,
+            },
+            previous_height: height,
+            altitude: None,
+        }
+    }
+
+    pub fn flying(position: Axial, altitude: f32) -> Self {
+        Transform {
+            altitude: Some(altitude),
+            ..Transform::new(position, altitude)
+        }
+    }
+
+    pub fn fractional(&self) -> FractionalAxial {
+        let next = self.next.unwrap_or(self.position);
+
+        FractionalAxial {
+            q: self.position.q as f32 + (next.q - self.position.q) as f32 * self.progress,
+            r: self.position.r as f32 + (next.r - self.position.r) as f32 * self.progress,
+        }
+    }
+
+    /// Height in levels, climbing smoothly between the two tiles of a step
+    pub fn height(&self, terrain: &HexMap<HexTileData>) -> f32 {
+        if let Some(altitude) = self.altitude {
+            return altitude;
+        }
+
+        let tile_height = |axial: Axial| {
+            terrain
+                .get_tile(axial.to_hex())
+                .map(|tile| tile.wall_height)
+                .unwrap_or(0) as f32
+        };
+
+        let from = tile_height(self.position);
+        let to = self.next.map(tile_height).unwrap_or(from);
+
+        from + (to - from) * self.progress
     }
 }
 
 #[derive(Copy, Clone, Debug)]
-pub struct Agent {}
+pub struct Agent {
+    /// Hexes moved per second
+    pub speed: f32,
+    /// Damage dealt to the base on reaching it
+    pub damage: u32,
+    pub movement: Movement,
+    /// The goals the agent heads for, and the base it damages on reaching one
+    pub goals: GoalSetId,
+}
 
 impl Agent {
-    pub fn new() -> Self {
-        Agent {}
+    pub fn new(speed: f32, damage: u32, movement: Movement, goals: GoalSetId) -> Self {
+        Agent {
+            speed,
+            damage,
+            movement,
+            goals,
+        }
+    }
+
+    /// The flow field the agent follows, `None` for flyers
+    pub fn flow_key(&self) -> Option<FlowKey> {
+        match self.movement {
+            Movement::Ground(profile) => Some(FlowKey {
+                goals: self.goals,
+                profile,
+            }),
+            Movement::Flying => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Health {
+    pub max: u32,
+    pub current: u32,
+}
+
+impl Health {
+    pub fn new(max: u32) -> Self {
+        Health { max, current: max }
+    }
+
+    pub fn damage(&mut self, amount: u32) {
+        self.current = self.current.saturating_sub(amount);
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current == 0
+    }
+
+    pub fn fraction(&self) -> f32 {
+        self.current as f32 / self.max as f32
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Base {
+    /// Agents reaching these goals damage this base
+    pub goals: GoalSetId,
+}
+
+impl Base {
+    pub fn new(goals: GoalSetId) -> Self {
+        Base { goals }
+    }
+}
+
+/// Which agent in range a tower shoots at
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TargetPolicy {
+    /// The agent closest to reaching the base
+    First,
+    /// The agent closest to the tower
+    Closest,
+    /// The agent with the most health left
+    Strongest,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TowerKind {
+    /// Shoots bolts that follow their target
+    Bolt,
+    /// Lobs shells at the hex its target is on, they splash every agent around it
+    Mortar,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Tower {
+    /// Range in hexes when built at height 0
+    pub range: u32,
+    pub damage: u32,
+    /// Ticks between shots
+    pub cooldown: u32,
+    pub timer: u32,
+    /// Single shots follow the target, splash shots land where it was when they were fired
+    pub impact: Impact,
+    pub policy: TargetPolicy,
+    pub target: Option<EntityId>,
+}
+
+impl Tower {
+    pub fn new(kind: TowerKind, policy: TargetPolicy) -> Self {
+        let (range, damage, cooldown, impact) = match kind {
+            TowerKind::Bolt => (TOWER_RANGE, TOWER_DAMAGE, TOWER_COOLDOWN, Impact::Single),
+            TowerKind::Mortar => (
+                MORTAR_RANGE,
+                MORTAR_DAMAGE,
+                MORTAR_COOLDOWN,
+                Impact::Splash {
+                    radius: MORTAR_SPLASH_RADIUS,
+                },
+            ),
+        };
+
+        Tower {
+            range,
+            damage,
+            cooldown,
+            timer: 0,
+            impact,
+            policy,
+            target: None,
+        }
+    }
+
+    /// Towers on higher tiles can see further
+    pub fn range_at(&self, tile: &HexTileData) -> u32 {
+        self.range + tile.wall_height as u32 * TOWER_RANGE_PER_LEVEL
     }
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ProjectileTarget {
+    /// Follows the entity, if it dies first the projectile lands where it was last seen
+    Entity(EntityId),
+    Hex(Axial),
+}
+
+/// What happens when a projectile lands
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Impact {
+    /// Damages the targeted entity, or one agent on the targeted hex
+    Single,
+    /// Damages every agent within `radius` hexes of where it lands
+    Splash { radius: u32 },
+}
+
+/// Something flying through the air towards a target along an arc, heights are in pixels
+#[derive(Copy, Clone, Debug)]
+pub struct Projectile {
+    pub target: ProjectileTarget,
+    pub impact: Impact,
+    pub damage: u32,
+    /// Hexes travelled per second
+    pub speed: f32,
+    pub position: FractionalAxial,
+    pub height: f32,
+    /// Where the projectile is heading, updated while following an entity
+    pub destination: FractionalAxial,
+    pub destination_height: f32,
+    pub launch_height: f32,
+    /// How far above the line between launch and destination the middle of the arc is
+    pub apex: f32,
+    /// Hexes travelled so far
+    pub travelled: f32,
+    /// Position and height at the end of the previous tick, rendering interpolates from here
+    pub previous: FractionalAxial,
+    pub previous_height: f32,
+}
+
+impl Projectile {
+    /// Height along the arc after travelling `travelled` hexes with `remaining` hexes to go
+    pub fn arc_height(&self, travelled: f32, remaining: f32) -> f32 {
+        let total = travelled + remaining;
+        let t = if total > 0. { travelled / total } else { 1. };
+
+        self.launch_height
+            + (self.destination_height - self.launch_height) * t
+            + self.apex * 4. * t * (1. - t)
+    }
+}
+
+/// A nest agents are spawned from, waves refer to nests by `id`
 #[derive(Copy, Clone, Debug)]
 pub struct Spawner {
-    pub period: u8,
-    pub counter: u8,
+    pub id: u32,
 }
 
 impl Spawner {
-    pub fn new(period: u8) -> Self {
-        Self { period, counter: 1 }
+    pub fn new(id: u32) -> Self {
+        Self { id }
+    }
+}
+
+/// Texture an entity is drawn with, looked up in `Drawables` when drawing so the
+/// simulation can run without any textures loaded
+#[derive(Clone, Debug)]
+pub enum Appearance {
+    Sprite(String),
+    Command(String),
+}
+
+impl Appearance {
+    pub fn to_sprite(&self, drawables: &Drawables) -> Sprite {
+        match self {
+            Appearance::Sprite(texture) => Sprite::new(drawables.alias[texture.as_str()]),
+            Appearance::Command(texture) => {
+                Sprite::from_command(DrawCommand::new(drawables.alias[texture.as_str()]))
+            }
+        }
+    }
+}
+
+/// Damage waiting to be applied to entities with `Health` on the next `apply_damage`
+#[derive(Clone, Debug, Default)]
+pub struct DamageQueue {
+    pub pending: HashMap<EntityId, u32>,
+}
+
+impl DamageQueue {
+    pub fn push(&mut self, entity: EntityId, amount: u32) {
+        *self.pending.entry(entity).or_insert(0) += amount;
     }
 }
+
+#[derive(Copy, Clone, Debug)]
+pub struct EditCosts {
+    pub raise: u32,
+    pub lower: u32,
+    pub tower: u32,
+}
+
+impl Default for EditCosts {
+    fn default() -> Self {
+        EditCosts {
+            raise: RAISE_COST,
+            lower: LOWER_COST,
+            tower: TOWER_COST,
+        }
+    }
+}
+
+/// Resources the player spends on terrain edits and towers
+#[derive(Copy, Clone, Debug)]
+pub struct Ledger {
+    pub resources: u32,
+    pub income_per_tick: u32,
+    pub income_per_kill: u32,
+    pub costs: EditCosts,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Ledger {
+            resources: STARTING_RESOURCES,
+            income_per_tick: INCOME_PER_TICK,
+            income_per_kill: INCOME_PER_KILL,
+            costs: EditCosts::default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DeathEvent {
+    pub entity: EntityId,
+}
+
+/// Entities that died during the current tick
+#[derive(Clone, Debug, Default)]
+pub struct Deaths {
+    pub events: Vec<DeathEvent>,
+}
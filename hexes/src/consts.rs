@@ -6,19 +6,91 @@ pub mod textures {
     pub const MARKER: &str = "marker";
     pub const ARROW_SHEET: &str = "arrows";
     pub const BASE: &str = "base";
-    pub const ENEMY: &str = "enemy";
     pub const NEST: &str = "nest-floor";
-
-    pub const COLOR_TINT: [f32; 4] = [0.4, 0.6, 0.8, 0.95];
+    pub const HEALTH_BAR: &str = "health-bar";
+    pub const TOWER: &str = "tower";
+    pub const PROJECTILE: &str = "projectile";
+    /// Digits 0 to 9 followed by `-` and `,`, `DIGIT_ADVANCE` pixels apart
+    pub const DIGITS: &str = "digits";
 }
 
 pub const CAM_SPEED: f32 = 5.0;
+/// Zoom levels the mouse wheel steps through. Only whole numbers so the pixel art stays crisp,
+/// which makes the default 1x the furthest out: below it every screen pixel would skip some
+/// texture pixels and thin lines in the sprites would flicker in and out while panning.
+pub const ZOOM_LEVELS: [f32; 4] = [1.0, 2.0, 3.0, 4.0];
+
+pub const TICKS_PER_SECOND: f64 = 30.0;
+pub const MAX_TICKS_PER_UPDATE: u32 = 5;
+
+pub const BASE_HEALTH: u32 = 100;
+/// Goal set of the base in the default level, and the one waves send agents to unless
+/// they name another
+pub const DEFAULT_GOALS: &str = "base";
+
+/// Walkers, or flyers, that fit on a hex at once
+pub const TILE_CAPACITY: usize = 2;
+
+pub const TOWER_RANGE: u32 = 3;
+pub const TOWER_RANGE_PER_LEVEL: u32 = 1;
+pub const TOWER_DAMAGE: u32 = 2;
+pub const TOWER_COOLDOWN: u32 = 15;
+pub const MORTAR_RANGE: u32 = 4;
+pub const MORTAR_DAMAGE: u32 = 3;
+pub const MORTAR_COOLDOWN: u32 = 45;
+/// Hexes around where a shell lands that it damages
+pub const MORTAR_SPLASH_RADIUS: u32 = 1;
+
+/// Hexes per second
+pub const PROJECTILE_SPEED: f32 = 8.0;
+/// Levels above the tile a tower launches projectiles from
+pub const PROJECTILE_LAUNCH_HEIGHT: f32 = 1.0;
+/// Levels the middle of an arc rises per hex travelled
+pub const PROJECTILE_ARC: f32 = 0.25;
+
+pub const STARTING_RESOURCES: u32 = 500;
+pub const INCOME_PER_TICK: u32 = 1;
+pub const INCOME_PER_KILL: u32 = 20;
+pub const RAISE_COST: u32 = 60;
+pub const LOWER_COST: u32 = 30;
+pub const TOWER_COST: u32 = 300;
+
+/// Levels flyers stay above the tallest tile on their way
+pub const FLIGHT_CLEARANCE: f32 = 2.0;
+
+/// Brightness of the lowest and tallest levels of the map, the ones between are spread evenly
+pub const TINT_LOWEST: f32 = 0.4;
+pub const TINT_TALLEST: f32 = 0.95;
+/// Direction the light shines from, in degrees clockwise from the right of the unrotated map
+pub const LIGHT_ANGLE: f32 = 60.0;
+/// Brightness of walls facing away from the light, relative to the tiles' tops
+pub const WALL_AMBIENT: f32 = 0.6;
+
+pub const HEALTH_BAR_WIDTH: f32 = 20.0;
+/// Distance above an entity's position the health bar is drawn at
+pub const HEALTH_BAR_OFFSET: f32 = 24.0;
+
+pub const DIGIT_WIDTH: f32 = 6.0;
+pub const DIGIT_HEIGHT: f32 = 10.0;
+pub const DIGIT_ADVANCE: f32 = 8.0;
 
 pub const MAX_FLOOR_HEIGHT: u8 = 2;
 pub const MAX_BRICK_HEIGHT: u8 = 3;
 
+pub const MAP_SEED: u64 = 0x5EED_4E58;
+
+/// Flow field cost of walking onto a tile at the same height or below
+pub const STEP_COST: u32 = 10;
+/// Extra cost of every level climbed
+pub const CLIMB_COST: u32 = 15;
+/// Extra cost of walking over bricks
+pub const BRICK_COST: u32 = 20;
+
 pub const WIDTH: usize = 2;
 pub const HEIGHT: usize = 2;
 
-pub const DRAW_DOTS: bool = false;
-pub const DRAW_FLOW: bool = false;
+pub const SAVE_PATH: &str = "map.hexsave";
+pub const WAVES_PATH: &str = "data/waves.txt";
+pub const ENEMIES_PATH: &str = "data/enemies.txt";
+
+pub const HEADLESS_TICKS: u64 = 1000;
@@ -0,0 +1,117 @@
+use std::{error::Error, fmt, io};
+
+/// Why a wave or enemy file couldn't be loaded
+#[derive(Debug)]
+pub enum DataError {
+    Io(io::Error),
+    /// A line couldn't be parsed
+    Malformed {
+        line: usize,
+    },
+    UnknownEnemy {
+        line: usize,
+        name: String,
+    },
+    /// A group came before the first wave
+    GroupOutsideWave {
+        line: usize,
+    },
+    NoWaves,
+    NoEnemies,
+    /// A group heads for a goal set the map doesn't have
+    UnknownGoals {
+        name: String,
+    },
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataError::Io(err) => write!(f, "could not read file: {}", err),
+            DataError::Malformed { line } => write!(f, "malformed line {}", line),
+            DataError::UnknownEnemy { line, name } => {
+                write!(f, "unknown enemy \"{}\" on line {}", name, line)
+            }
+            DataError::GroupOutsideWave { line } => {
+                write!(f, "group on line {} isn't part of a wave", line)
+            }
+            DataError::NoWaves => write!(f, "no waves defined"),
+            DataError::NoEnemies => write!(f, "no enemies defined"),
+            DataError::UnknownGoals { name } => {
+                write!(
+                    f,
+                    "waves head for \"{}\" but the map has no such goals",
+                    name
+                )
+            }
+        }
+    }
+}
+
+impl Error for DataError {}
+
+impl From<io::Error> for DataError {
+    fn from(err: io::Error) -> Self {
+        DataError::Io(err)
+    }
+}
+
+/// The non empty lines of a data file that aren't `#` comments, with their line numbers
+pub fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+}
+
+pub fn parse<T: std::str::FromStr>(word: Option<&str>, line: usize) -> Result<T, DataError> {
+    word.and_then(|word| word.parse().ok())
+        .ok_or(DataError::Malformed { line })
+}
+
+/// The `key=value` pairs following the keyword of a line
+pub struct Fields<'a> {
+    line: usize,
+    pairs: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Fields<'a> {
+    pub fn parse<I: Iterator<Item = &'a str>>(words: I, line: usize) -> Result<Self, DataError> {
+        let mut pairs = vec![];
+        for word in words {
+            let mut pair = word.splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some(key), Some(value)) => pairs.push((key, value)),
+                _ => return Err(DataError::Malformed { line }),
+            }
+        }
+
+        Ok(Fields { line, pairs })
+    }
+
+    pub fn get(&self, key: &str) -> Result<&'a str, DataError> {
+        self.optional(key)
+            .ok_or(DataError::Malformed { line: self.line })
+    }
+
+    /// Like `get` for keys that can be left out
+    pub fn optional(&self, key: &str) -> Option<&'a str> {
+        self.pairs
+            .iter()
+            .find(|(found, _)| *found == key)
+            .map(|&(_, value)| value)
+    }
+
+    pub fn value<T: std::str::FromStr>(&self, key: &str) -> Result<T, DataError> {
+        parse(Some(self.get(key)?), self.line)
+    }
+
+    /// A `yes` or `no` value
+    pub fn flag(&self, key: &str) -> Result<bool, DataError> {
+        match self.get(key)? {
+            "yes" => Ok(true),
+            "no" => Ok(false),
+            _ => Err(DataError::Malformed { line: self.line }),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+
+use vermarine_lib::{hexmap::Axial, shipyard::*};
+
+use crate::{
+    archetypes::Archetypes,
+    components::{Ledger, Spawner, TargetPolicy, TowerKind, Transform},
+    consts::*,
+    entity_creator::{self, PlacementError},
+    map::{FlowKey, HexTileData, Map},
+};
+
+/// A change the player wants to make to the map, paid for from the `Ledger`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TerrainEdit {
+    /// Add a level of bricks
+    Raise(Axial),
+    /// Remove a level of bricks, or of ground once there are no bricks left
+    Lower(Axial),
+    PlaceTower(Axial, TowerKind, TargetPolicy),
+}
+
+impl TerrainEdit {
+    pub fn position(&self) -> Axial {
+        match *self {
+            TerrainEdit::Raise(position)
+            | TerrainEdit::Lower(position)
+            | TerrainEdit::PlaceTower(position, ..) => position,
+        }
+    }
+
+    pub fn cost(&self, ledger: &Ledger) -> u32 {
+        match self {
+            TerrainEdit::Raise(_) => ledger.costs.raise,
+            TerrainEdit::Lower(_) => ledger.costs.lower,
+            TerrainEdit::PlaceTower(..) => ledger.costs.tower,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EditError {
+    NoTile,
+    /// The tile is already at `MAX_BRICK_HEIGHT`
+    TooHigh,
+    /// The tile is already at height 0
+    TooLow,
+    CantAfford {
+        cost: u32,
+        available: u32,
+    },
+    /// The nest at this position would have no way to reach a goal
+    BlocksPath(Axial),
+    Placement(PlacementError),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::NoTile => write!(f, "there is no tile there"),
+            EditError::TooHigh => write!(f, "the tile can't be raised any higher"),
+            EditError::TooLow => write!(f, "the tile can't be lowered any further"),
+            EditError::CantAfford { cost, available } => {
+                write!(f, "it costs {} but only {} is available", cost, available)
+            }
+            EditError::BlocksPath(nest) => write!(
+                f,
+                "the nest at ({}, {}) would be cut off from the base",
+                nest.q, nest.r
+            ),
+            EditError::Placement(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<PlacementError> for EditError {
+    fn from(err: PlacementError) -> Self {
+        EditError::Placement(err)
+    }
+}
+
+fn raise(tile: &mut HexTileData) -> Result<(), EditError> {
+    if tile.ground_height > tile.wall_height {
+        tile.wall_height = tile.ground_height + 1;
+    } else if tile.wall_height < MAX_BRICK_HEIGHT {
+        tile.wall_height += 1;
+    } else {
+        return Err(EditError::TooHigh);
+    }
+
+    Ok(())
+}
+
+fn lower(tile: &mut HexTileData) -> Result<(), EditError> {
+    if tile.ground_height > tile.wall_height && tile.ground_height > 0 {
+        tile.ground_height -= 1;
+    } else if tile.wall_height > tile.ground_height {
+        tile.wall_height -= 1;
+    } else if tile.wall_height > 0 {
+        tile.wall_height -= 1;
+        tile.ground_height -= 1;
+    } else {
+        return Err(EditError::TooLow);
+    }
+
+    Ok(())
+}
+
+/// The tile a terrain edit would leave behind, `None` when placing a tower
+fn proposed_tile(
+    edit: TerrainEdit,
+    world: &mut AllStoragesViewMut,
+) -> Result<Option<HexTileData>, EditError> {
+    let position = edit.position();
+
+    let tile = if let TerrainEdit::PlaceTower(..) = edit {
+        entity_creator::check_tower_placement(position, world)?;
+        None
+    } else {
+        let tile = world.run(
+            |map: UniqueView<Map>,
+             archetypes: UniqueView<Archetypes>,
+             spawners: View<Spawner>,
+             transforms: View<Transform>| {
+                let hex = position.to_hex();
+                let mut tile = *map.terrain.get_tile(hex).ok_or(EditError::NoTile)?;
+
+                match edit {
+                    TerrainEdit::Raise(_) => raise(&mut tile)?,
+                    TerrainEdit::Lower(_) => lower(&mut tile)?,
+                    TerrainEdit::PlaceTower(..) => unreachable!(),
+                }
+
+                let nests: Vec<Axial> = (&spawners, &transforms)
+                    .iter()
+                    .map(|(_, transform)| transform.position)
+                    .collect();
+
+                // Every kind of enemy has to be able to reach every goal set from every nest
+                // it could reach it from before
+                for goals in map.goal_set_ids() {
+                    for profile in archetypes.profiles() {
+                        let key = FlowKey { goals, profile };
+                        if let Some(nest) = map.cut_off_by(hex, tile, &nests, key) {
+                            return Err(EditError::BlocksPath(nest));
+                        }
+                    }
+                }
+
+                Ok(tile)
+            },
+        )?;
+        Some(tile)
+    };
+
+    world.run(|ledger: UniqueView<Ledger>| {
+        let cost = edit.cost(&ledger);
+        if ledger.resources < cost {
+            return Err(EditError::CantAfford {
+                cost,
+                available: ledger.resources,
+            });
+        }
+
+        Ok(())
+    })?;
+
+    Ok(tile)
+}
+
+/// Checks everything `apply_edit` would without changing anything
+pub fn check_edit(edit: TerrainEdit, world: &mut AllStoragesViewMut) -> Result<(), EditError> {
+    proposed_tile(edit, world).map(|_| ())
+}
+
+/// Checks an edit can be made and paid for, then makes it. Nothing is changed or spent
+/// when it's rejected.
+pub fn apply_edit(edit: TerrainEdit, world: &mut AllStoragesViewMut) -> Result<(), EditError> {
+    match (edit, proposed_tile(edit, world)?) {
+        (TerrainEdit::PlaceTower(position, kind, policy), _) => {
+            entity_creator::create_tower(position, kind, policy, world)?;
+        }
+        (_, Some(tile)) => world.run(|mut map: UniqueViewMut<Map>| {
+            let hex = edit.position().to_hex();
+            map.terrain.set_tile(hex, tile);
+            if tile.wall_height > map.terrain.tallest {
+                map.terrain.tallest = tile.wall_height;
+            }
+            if tile.wall_height < map.lowest {
+                map.lowest = tile.wall_height;
+            }
+            map.repair_dijkstra(hex);
+        }),
+        (_, None) => unreachable!(),
+    }
+
+    world.run(|mut ledger: UniqueViewMut<Ledger>| {
+        let cost = edit.cost(&ledger);
+        ledger.resources -= cost;
+    });
+
+    Ok(())
+}
+
+/// The tile under the cursor and whether raising it would cut a nest off, drawn in red
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EditPreview {
+    pub hovered: Option<Axial>,
+    pub blocked: bool,
+    /// Tile `blocked` was worked out for, checking means searching every flow field so it's
+    /// only redone when the cursor moves or the terrain changes
+    checked: Option<Axial>,
+}
+
+impl EditPreview {
+    /// Moves the preview to `hovered`, returns whether `blocked` has to be worked out again
+    pub fn hover(&mut self, hovered: Option<Axial>) -> bool {
+        self.hovered = hovered;
+        if hovered.is_none() {
+            self.blocked = false;
+            self.checked = None;
+        }
+
+        hovered.is_some() && hovered != self.checked
+    }
+
+    pub fn set_blocked(&mut self, blocked: bool) {
+        self.blocked = blocked;
+        self.checked = self.hovered;
+    }
+
+    /// The terrain changed so the last check no longer holds
+    pub fn invalidate(&mut self) {
+        self.checked = None;
+    }
+}
@@ -1,37 +1,64 @@
+use std::fmt;
+
 use vermarine_lib::{
-    hexmap::Axial,
-    rendering::{draw_buffer::DrawCommand, Drawables, Sprite},
+    hexmap::{Axial, FractionalAxial},
     shipyard::*,
 };
 
-use crate::components::{Agent, Spawner, Transform};
+use crate::components::{
+    Agent, Appearance, Base, Health, Impact, Projectile, ProjectileTarget, Spawner, TargetPolicy,
+    Tower, TowerKind, Transform,
+};
 
-use crate::map::Map;
+use crate::map::{fractional_distance, hex_distance, round_fractional, GoalSetId, Map, Movement};
 
 use crate::consts::*;
 
-pub fn create_agent(position: Axial, world: &mut AllStoragesViewMut) {
-    let sprite = world
-        .run(|drawables: NonSendSync<UniqueViewMut<Drawables>>| drawables.alias[textures::ENEMY]);
+use crate::archetypes::Archetype;
 
-    world
-        .entity_builder()
-        .with(Transform::new(position))
-        .with(Sprite::new(sprite))
-        .with(Agent::new())
-        .build();
+fn tile_height(position: Axial, world: &mut AllStoragesViewMut) -> f32 {
+    world.run(|map: UniqueView<Map>| {
+        map.terrain
+            .get_tile(position.to_hex())
+            .map(|tile| tile.wall_height)
+            .unwrap_or(0) as f32
+    })
 }
 
-pub fn create_base(position: Axial, world: &mut AllStoragesViewMut) {
-    let sprite =
-        world.run(|drawables: NonSendSync<UniqueView<Drawables>>| drawables.alias[textures::BASE]);
+/// Spawns an agent with the archetype's health multiplied by `scale`
+pub fn create_agent(
+    position: Axial,
+    archetype: &Archetype,
+    goals: GoalSetId,
+    scale: f32,
+    world: &mut AllStoragesViewMut,
+) -> EntityId {
+    let transform = match archetype.movement {
+        Movement::Ground(_) => Transform::new(position, tile_height(position, world)),
+        Movement::Flying => {
+            let altitude = world.run(|map: UniqueView<Map>| map.flight_altitude(position, goals));
+            Transform::flying(position, altitude)
+        }
+    };
+    let health = (archetype.health as f32 * scale).round().max(1.) as u32;
 
     world
         .entity_builder()
-        .with(Transform::new(position))
-        .with(Sprite::from_command(DrawCommand::new(sprite)))
-        .build();
+        .with(transform)
+        .with(Appearance::Sprite(archetype.sprite.clone()))
+        .with(Agent::new(
+            archetype.speed,
+            archetype.damage,
+            archetype.movement,
+            goals,
+        ))
+        .with(Health::new(health))
+        .build()
+}
 
+/// Flattens the ground for a base and creates it along with the goal set called `goals`
+/// agents seeking it walk to
+pub fn create_base(position: Axial, goals: &str, world: &mut AllStoragesViewMut) {
     world.run(|mut map: UniqueViewMut<Map>| {
         let desired_height = 2;
 
@@ -49,23 +76,169 @@ pub fn create_base(position: Axial, world: &mut AllStoragesViewMut) {
         if map.terrain.tallest < desired_height {
             map.terrain.tallest = desired_height;
         }
+        if map.lowest > desired_height {
+            map.lowest = desired_height;
+        }
 
-        let goals = vec![
+        // Flattening changed the terrain under the existing goal sets' fields
+        map.refresh_dijkstra();
+    });
+
+    spawn_base(position, goals, world);
+}
+
+/// Creates a base on the terrain as it is, for bases whose ground was flattened when they
+/// were first created, like ones loaded from a save
+pub fn spawn_base(position: Axial, goals: &str, world: &mut AllStoragesViewMut) {
+    let goals = world.run(|mut map: UniqueViewMut<Map>| {
+        let hexes = vec![
             position.to_hex() + Axial::new(0, 1),
             position.to_hex() + Axial::new(1, 1),
         ];
-        map.update_dijkstra(goals);
+        map.set_goals(goals, hexes)
     });
+
+    let height = tile_height(position, world);
+
+    world
+        .entity_builder()
+        .with(Transform::new(position, height))
+        .with(Appearance::Command(textures::BASE.to_string()))
+        .with(Base::new(goals))
+        .with(Health::new(BASE_HEALTH))
+        .build();
 }
 
-pub fn create_nest(postion: Axial, timer: u8, world: &mut AllStoragesViewMut) {
-    let sprite =
-        world.run(|drawables: NonSendSync<UniqueView<Drawables>>| drawables.alias[textures::NEST]);
+pub fn create_nest(postion: Axial, id: u32, world: &mut AllStoragesViewMut) {
+    let height = tile_height(postion, world);
 
     world
         .entity_builder()
-        .with(Spawner::new(timer))
-        .with(Transform::new(postion))
-        .with(Sprite::new(sprite))
+        .with(Spawner::new(id))
+        .with(Transform::new(postion, height))
+        .with(Appearance::Sprite(textures::NEST.to_string()))
         .build();
 }
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PlacementError {
+    NoTile,
+    /// Towers can only be built on tiles level with their neighbours
+    NotFlat,
+    /// Agents need to be able to stand on goals
+    OnGoal,
+    /// There's already a tower, nest or the base on the tile
+    Occupied,
+}
+
+impl fmt::Display for PlacementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlacementError::NoTile => write!(f, "there is no tile there"),
+            PlacementError::NotFlat => write!(f, "the tile isn't flat"),
+            PlacementError::OnGoal => write!(f, "the tile is a goal"),
+            PlacementError::Occupied => write!(f, "the tile is already occupied"),
+        }
+    }
+}
+
+pub fn check_tower_placement(
+    position: Axial,
+    world: &mut AllStoragesViewMut,
+) -> Result<(), PlacementError> {
+    world.run(
+        |map: UniqueView<Map>,
+         transforms: View<Transform>,
+         towers: View<Tower>,
+         bases: View<Base>,
+         spawners: View<Spawner>| {
+            if map.terrain.get_tile(position.to_hex()).is_none() {
+                return Err(PlacementError::NoTile);
+            }
+            if !map.is_flat(position.to_hex()) {
+                return Err(PlacementError::NotFlat);
+            }
+            if map.is_any_goal(position.to_hex()) {
+                return Err(PlacementError::OnGoal);
+            }
+
+            let occupied = |other: Axial| hex_distance(other, position) == 0;
+            for (_, transform) in (&towers, &transforms).iter() {
+                if occupied(transform.position) {
+                    return Err(PlacementError::Occupied);
+                }
+            }
+            for (_, transform) in (&bases, &transforms).iter() {
+                if occupied(transform.position) {
+                    return Err(PlacementError::Occupied);
+                }
+            }
+            for (_, transform) in (&spawners, &transforms).iter() {
+                if occupied(transform.position) {
+                    return Err(PlacementError::Occupied);
+                }
+            }
+
+            Ok(())
+        },
+    )
+}
+
+pub fn create_tower(
+    position: Axial,
+    kind: TowerKind,
+    policy: TargetPolicy,
+    world: &mut AllStoragesViewMut,
+) -> Result<EntityId, PlacementError> {
+    check_tower_placement(position, world)?;
+
+    let height = tile_height(position, world);
+
+    Ok(world
+        .entity_builder()
+        .with(Transform::new(position, height))
+        .with(Appearance::Sprite(textures::TOWER.to_string()))
+        .with(Tower::new(kind, policy))
+        .build())
+}
+
+/// Launches a projectile from above the tile at `origin`, the arc is scaled by how far
+/// `destination` is and the heights of both tiles
+pub fn create_projectile(
+    origin: Axial,
+    target: ProjectileTarget,
+    destination: FractionalAxial,
+    damage: u32,
+    impact: Impact,
+    world: &mut AllStoragesViewMut,
+) -> EntityId {
+    let depth_step = world.run(|map: UniqueView<Map>| map.terrain.hex_depth_step);
+    let launch_height = (tile_height(origin, world) + PROJECTILE_LAUNCH_HEIGHT) * depth_step;
+    let destination_height = tile_height(round_fractional(destination), world) * depth_step;
+
+    let position = FractionalAxial {
+        q: origin.q as f32,
+        r: origin.r as f32,
+    };
+    let apex = fractional_distance(position, destination) * PROJECTILE_ARC * depth_step;
+
+    world
+        .entity_builder()
+        .with(Projectile {
+            target,
+            impact,
+            damage,
+            speed: PROJECTILE_SPEED,
+            position,
+            height: launch_height,
+            destination,
+            destination_height,
+            launch_height,
+            apex,
+            travelled: 0.,
+            previous: position,
+            previous_height: launch_height,
+        })
+        .with(Appearance::Sprite(textures::PROJECTILE.to_string()))
+        .build()
+}
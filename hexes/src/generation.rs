@@ -0,0 +1,164 @@
+use vermarine_lib::hexmap::{CHUNK_HEIGHT, CHUNK_WIDTH};
+
+use crate::consts::*;
+use crate::map::{chunk_tile_position, HexTileData};
+
+pub type ChunkTiles = [Option<HexTileData>; CHUNK_WIDTH * CHUNK_HEIGHT];
+
+/// Fills the tiles of a chunk when a `Map` is created.
+pub trait TerrainGenerator {
+    fn generate_chunk(&mut self, chunk_q: i32, chunk_r: i32) -> ChunkTiles;
+}
+
+/// Layered value noise quantized into floor heights, which gives hills and valleys
+/// with flat plateaus between the height steps. The same seed always gives the same terrain.
+pub struct NoiseGenerator {
+    pub seed: u64,
+    /// Number of noise layers, each at double the frequency of the last
+    pub octaves: u32,
+    /// Size in tiles of a cell of the first layer
+    pub scale: f32,
+    /// Amplitude multiplier applied to every successive layer
+    pub persistence: f32,
+}
+
+impl NoiseGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            octaves: 3,
+            scale: 12.,
+            persistence: 0.5,
+        }
+    }
+
+    pub fn sample(&self, x: f32, y: f32) -> f32 {
+        let mut total = 0.;
+        let mut amplitude = 1.;
+        let mut max_amplitude = 0.;
+        let mut frequency = 1. / self.scale;
+
+        for octave in 0..self.octaves {
+            let seed = self
+                .seed
+                .wrapping_add((octave as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            total += value_noise(seed, x * frequency, y * frequency) * amplitude;
+            max_amplitude += amplitude;
+            amplitude *= self.persistence;
+            frequency *= 2.;
+        }
+
+        if max_amplitude > 0. {
+            total / max_amplitude
+        } else {
+            0.
+        }
+    }
+}
+
+impl TerrainGenerator for NoiseGenerator {
+    fn generate_chunk(&mut self, chunk_q: i32, chunk_r: i32) -> ChunkTiles {
+        let mut tiles = [None; CHUNK_WIDTH * CHUNK_HEIGHT];
+
+        for (index, tile) in tiles.iter_mut().enumerate() {
+            let axial = chunk_tile_position(chunk_q, chunk_r, index);
+
+            // Sample in cartesian space so the noise isn't skewed along the axial axes
+            let x = axial.q as f32 + axial.r as f32 * 0.5;
+            let y = axial.r as f32 * 0.866;
+
+            let levels = MAX_FLOOR_HEIGHT as f32 + 1.;
+            let value = (self.sample(x, y) * levels)
+                .floor()
+                .min(levels - 1.)
+                .max(0.) as u8;
+            *tile = Some(HexTileData::new(value));
+        }
+
+        tiles
+    }
+}
+
+fn value_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let (tx, ty) = (smoothstep(x - x0), smoothstep(y - y0));
+
+    let top = lerp(
+        lattice_value(seed, ix, iy),
+        lattice_value(seed, ix + 1, iy),
+        tx,
+    );
+    let bottom = lerp(
+        lattice_value(seed, ix, iy + 1),
+        lattice_value(seed, ix + 1, iy + 1),
+        tx,
+    );
+
+    lerp(top, bottom, ty)
+}
+
+/// Hashes a lattice point into the range [0, 1)
+fn lattice_value(seed: u64, x: i32, y: i32) -> f32 {
+    let mut hash = seed
+        ^ (x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xC4CE_B9FE_1A85_EC53);
+    hash ^= hash >> 33;
+
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3. - 2. * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heights(generator: &mut dyn TerrainGenerator) -> Vec<(u8, u8)> {
+        let mut heights = vec![];
+        for chunk_r in -1..1 {
+            for chunk_q in -1..1 {
+                for tile in generator.generate_chunk(chunk_q, chunk_r).iter() {
+                    let tile = tile.expect("generated chunks have every tile");
+                    heights.push((tile.ground_height, tile.wall_height));
+                }
+            }
+        }
+        heights
+    }
+
+    #[test]
+    fn same_seed_same_terrain() {
+        let first = heights(&mut NoiseGenerator::new(MAP_SEED));
+        let second = heights(&mut NoiseGenerator::new(MAP_SEED));
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn different_seed_different_terrain() {
+        let first = heights(&mut NoiseGenerator::new(MAP_SEED));
+        let second = heights(&mut NoiseGenerator::new(MAP_SEED + 1));
+
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn heights_stay_within_floor_range() {
+        for (ground, wall) in heights(&mut NoiseGenerator::new(MAP_SEED)) {
+            assert!(ground <= MAX_FLOOR_HEIGHT);
+            assert_eq!(ground, wall);
+        }
+    }
+}
@@ -1,108 +1,280 @@
+mod archetypes;
+mod bench;
 mod components;
 mod consts;
+mod data;
+mod edits;
 mod entity_creator;
+mod generation;
 mod map;
+mod occupancy;
+mod overlays;
+mod save;
+mod shading;
+mod simulation;
 mod systems;
+mod waves;
 
-use map::Map;
+use archetypes::Archetypes;
+use components::TargetPolicy;
+use edits::EditPreview;
+use overlays::DebugOverlays;
+use save::{SaveData, SaveFormat};
+use simulation::{GameState, Simulation};
+use waves::WaveSchedule;
 
 use vermarine_lib::{
-    hexmap::Axial,
     rendering::{draw_buffer::DrawBuffer, Drawables},
     shipyard::{self, *},
     tetra::{
         self,
         graphics::{self, Camera, Color},
-        input::InputContext,
-        Context, ContextBuilder, State,
+        input::{self, InputContext, Key},
+        time, window, Context, ContextBuilder, State,
     },
 };
 
 fn main() -> tetra::Result {
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.iter().any(|arg| arg == "--bench-flow") {
+        bench::flow_field();
+        return Ok(());
+    }
+
+    let enemies_path = args
+        .iter()
+        .position(|arg| arg == "--enemies")
+        .and_then(|index| args.get(index + 1))
+        .map_or(consts::ENEMIES_PATH, |path| path.as_str());
+    let archetypes = load_archetypes(enemies_path);
+
+    let waves_path = args
+        .iter()
+        .position(|arg| arg == "--waves")
+        .and_then(|index| args.get(index + 1))
+        .map_or(consts::WAVES_PATH, |path| path.as_str());
+    let waves = load_waves(waves_path, &archetypes);
+
+    if let Some(index) = args.iter().position(|arg| arg == "--headless") {
+        let ticks = args
+            .get(index + 1)
+            .and_then(|ticks| ticks.parse().ok())
+            .unwrap_or(consts::HEADLESS_TICKS);
+
+        let (mut simulation, _) = start_scenario(&archetypes, waves);
+        for _ in 0..ticks {
+            simulation.tick();
+        }
+
+        let next_wave = simulation
+            .ticks_to_next_wave()
+            .map_or("spawning".to_string(), |ticks| {
+                format!("in {} ticks", ticks)
+            });
+        println!(
+            "Ran {} ticks, wave {}, next wave {}, {} agents alive, base health {}, {:?}",
+            simulation.ticks(),
+            simulation.wave(),
+            next_wave,
+            simulation.agent_count(),
+            simulation.base_health(),
+            simulation.state()
+        );
+        return Ok(());
+    }
+
     ContextBuilder::new("Hexes", 1280, 720)
         .show_mouse(true)
         .resizable(true)
-        .timestep(tetra::time::Timestep::Variable)
+        .timestep(time::Timestep::Variable)
         .vsync(true)
         .build()?
-        .run(Game::new)
+        .run(|ctx| Game::new(ctx, archetypes, waves))
+}
+
+fn load_archetypes(path: &str) -> Archetypes {
+    Archetypes::load_file(path).unwrap_or_else(|err| {
+        eprintln!(
+            "Failed to load {}: {}, using the built in enemies",
+            path, err
+        );
+        Archetypes::builtin()
+    })
+}
+
+fn load_waves(path: &str, archetypes: &Archetypes) -> WaveSchedule {
+    WaveSchedule::load_file(path, archetypes).unwrap_or_else(|err| {
+        eprintln!("Failed to load {}: {}, using the built in waves", path, err);
+        WaveSchedule::builtin(archetypes).expect("built in waves use unknown enemies")
+    })
+}
+
+/// Starts the default level, falling back to the built in waves if `waves` head for goals
+/// it doesn't have
+fn start_scenario(archetypes: &Archetypes, waves: WaveSchedule) -> (Simulation, WaveSchedule) {
+    match Simulation::scenario(consts::MAP_SEED, archetypes.clone(), waves.clone()) {
+        Ok(simulation) => (simulation, waves),
+        Err(err) => {
+            eprintln!(
+                "Failed to start the level: {}, using the built in waves",
+                err
+            );
+            let waves =
+                WaveSchedule::builtin(archetypes).expect("built in waves use unknown enemies");
+            let simulation =
+                Simulation::scenario(consts::MAP_SEED, archetypes.clone(), waves.clone())
+                    .expect("built in waves head for unknown goals");
+            (simulation, waves)
+        }
+    }
 }
 
 pub struct Game {
-    world: World,
+    simulation: Simulation,
+    archetypes: Archetypes,
+    waves: WaveSchedule,
+    /// Status last shown in the window title
+    title: String,
 }
 
 impl Game {
-    pub fn new(ctx: &mut Context) -> tetra::Result<Self> {
-        let world = World::new();
+    pub fn new(
+        ctx: &mut Context,
+        archetypes: Archetypes,
+        waves: WaveSchedule,
+    ) -> tetra::Result<Self> {
+        let (simulation, waves) = start_scenario(&archetypes, waves);
+        Game::add_presentation(ctx, &simulation.world);
+
+        Ok(Game {
+            simulation,
+            archetypes,
+            waves,
+            title: String::new(),
+        })
+    }
 
-        world.add_unique(Map::new());
+    /// Adds the uniques needed to take input and draw on top of a simulation's world
+    fn add_presentation(ctx: &mut Context, world: &World) {
         world.add_unique((*ctx.input_context()).clone());
         world.add_unique_non_send_sync(Drawables::new(ctx).unwrap());
 
-        world.run(|mut all_storages| {
-            entity_creator::create_base(Axial::new(10, 5), &mut all_storages);
-
-            let nest_time = 20;
-
-            entity_creator::create_nest(Axial::new(-5, -7), nest_time, &mut all_storages);
-            entity_creator::create_nest(Axial::new(12, -15), nest_time, &mut all_storages);
-            entity_creator::create_nest(Axial::new(-12, -5), nest_time, &mut all_storages);
-            entity_creator::create_nest(Axial::new(2, -8), nest_time, &mut all_storages);
-            entity_creator::create_nest(Axial::new(-8, 6), nest_time, &mut all_storages);
-            entity_creator::create_nest(Axial::new(-5, -15), nest_time, &mut all_storages);
-            entity_creator::create_nest(Axial::new(11, 14), nest_time, &mut all_storages);
-            entity_creator::create_nest(Axial::new(5, 13), nest_time, &mut all_storages);
-            entity_creator::create_nest(Axial::new(2, 4), nest_time, &mut all_storages);
-            entity_creator::create_nest(Axial::new(14, -3), nest_time, &mut all_storages);
-        });
-
         let mut camera = Camera::with_window_size(ctx);
         camera.zoom = 1.0;
         world.add_unique(camera);
 
         world.add_unique(DrawBuffer::new());
+        world.add_unique(TargetPolicy::First);
+        world.add_unique(EditPreview::default());
+        world.add_unique(DebugOverlays::default());
+    }
 
-        Ok(Game { world })
+    fn save(&self, format: SaveFormat) {
+        if let Err(err) =
+            SaveData::capture(&self.simulation.world).save_file(consts::SAVE_PATH, format)
+        {
+            eprintln!("Failed to save map: {}", err);
+        }
+    }
+
+    fn load(&mut self, ctx: &mut Context) {
+        let save = match SaveData::load_file(consts::SAVE_PATH) {
+            Ok(save) => save,
+            Err(err) => {
+                eprintln!("Failed to load map: {}", err);
+                return;
+            }
+        };
+
+        let simulation =
+            match Simulation::from_save(&save, self.archetypes.clone(), self.waves.clone()) {
+                Ok(simulation) => simulation,
+                Err(err) => {
+                    eprintln!("Failed to load map: {}", err);
+                    return;
+                }
+            };
+        Game::add_presentation(ctx, &simulation.world);
+        self.simulation = simulation;
+    }
+
+    /// Shows the current wave, the base's health and how long until the next wave starts in
+    /// the window title
+    fn update_title(&mut self, ctx: &mut Context) {
+        let mut title = format!(
+            "Hexes - Wave {} - Base {} HP",
+            self.simulation.wave(),
+            self.simulation.base_health()
+        );
+        if let Some(ticks) = self.simulation.ticks_to_next_wave() {
+            let seconds = (ticks as f64 / consts::TICKS_PER_SECOND).ceil();
+            title.push_str(&format!(" - Next wave in {}s", seconds));
+        }
+
+        if title != self.title {
+            window::set_title(ctx, &title);
+            self.title = title;
+        }
     }
 }
 
 impl State for Game {
     fn update(&mut self, ctx: &mut Context) -> tetra::Result {
         let input_ctx = (*ctx.input_context()).clone();
-        self.world.run(|mut ctx: UniqueViewMut<InputContext>| {
+
+        if input::is_key_pressed(&input_ctx, Key::F5) {
+            self.save(SaveFormat::Text);
+        } else if input::is_key_pressed(&input_ctx, Key::F6) {
+            self.save(SaveFormat::Binary);
+        } else if input::is_key_pressed(&input_ctx, Key::F9) {
+            self.load(ctx);
+        }
+
+        let world = &self.simulation.world;
+        world.run(|mut ctx: UniqueViewMut<InputContext>| {
             *ctx = input_ctx;
         });
 
-        self.world.run(systems::move_camera);
-        self.world.run(systems::update_hex_map);
-        self.world.run(systems::move_agents);
-        self.world.run(systems::spawn_agents);
+        world.run(systems::toggle_overlays);
+        world.run(systems::move_camera);
+        world.run(systems::rotate_view);
+        world.run(systems::update_hex_map);
+        world.run(systems::place_towers);
+
+        self.simulation
+            .advance(time::get_delta_time(ctx).as_secs_f64());
+
+        self.update_title(ctx);
 
         Ok(())
     }
 
     fn event(&mut self, _: &mut Context, event: tetra::Event) -> tetra::Result {
-        match event {
-            tetra::Event::Resized { width, height } => {
-                self.world.run(|mut camera: UniqueViewMut<Camera>| {
+        if let tetra::Event::Resized { width, height } = event {
+            self.simulation
+                .world
+                .run(|mut camera: UniqueViewMut<Camera>| {
                     camera.set_viewport_size((width & !1) as f32, (height & !1) as f32);
                     camera.update();
                 });
-            }
-            _ => {}
         }
 
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
-        graphics::clear(ctx, Color::rgb(0.4, 0.6, 0.9));
+        let background = match self.simulation.state() {
+            GameState::Playing => Color::rgb(0.4, 0.6, 0.9),
+            GameState::Defeat => Color::rgb(0.6, 0.2, 0.2),
+        };
+        graphics::clear(ctx, background);
 
-        self.world.run(systems::draw_hex_map);
-        //self.world.run(systems::draw_agent_paths);
+        self.simulation.world.run(systems::draw_hex_map);
+        self.simulation.world.run(systems::draw_overlays);
+        self.simulation.world.run(systems::draw_flyers);
 
-        self.world.run(
+        self.simulation.world.run(
             |mut camera: UniqueViewMut<Camera>, mut draw_buff: UniqueViewMut<DrawBuffer>| {
                 camera.position.floor();
                 camera.update();
@@ -110,7 +282,7 @@ impl State for Game {
             },
         );
 
-        self.world.run_with_data(DrawBuffer::flush, ctx);
+        self.simulation.world.run_with_data(DrawBuffer::flush, ctx);
 
         Ok(())
     }
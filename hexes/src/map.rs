@@ -1,10 +1,16 @@
-use vermarine_lib::hexmap::{Axial, Hex, HexChunk, HexMap, CHUNK_HEIGHT, CHUNK_WIDTH};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+    fmt,
+};
 
-use rand::rngs::StdRng;
-use rand::Rng;
-use rand::SeedableRng;
+use vermarine_lib::{
+    hexmap::{Axial, FractionalAxial, Hex, HexChunk, HexMap, CHUNK_HEIGHT, CHUNK_WIDTH},
+    tetra::math::Vec2,
+};
 
 use crate::consts::*;
+use crate::generation::{ChunkTiles, TerrainGenerator};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum HexPathNode {
@@ -16,6 +22,8 @@ pub enum HexPathNode {
     Left,
 
     Goal,
+    /// Left behind when a repair cuts a tile off from every goal
+    Unreachable,
 }
 
 impl HexPathNode {
@@ -37,7 +45,7 @@ impl HexPathNode {
         }
     }
 
-    pub fn to_hex(&self) -> Hex {
+    pub fn to_hex(self) -> Hex {
         let (q, r) = match self {
             HexPathNode::TopLeft => (0, -1),
             HexPathNode::TopRight => (1, -1),
@@ -45,20 +53,189 @@ impl HexPathNode {
             HexPathNode::BottomRight => (0, 1),
             HexPathNode::BottomLeft => (-1, 1),
             HexPathNode::Left => (-1, 0),
-            HexPathNode::Goal => (0, 0),
+            HexPathNode::Goal | HexPathNode::Unreachable => (0, 0),
         };
 
         Axial::new(q, r).to_hex()
     }
+
+    /// The direction this points on screen when the map is turned by `rotation`
+    pub fn rotated(self, rotation: Rotation) -> HexPathNode {
+        const CLOCKWISE: [HexPathNode; 6] = [
+            HexPathNode::Right,
+            HexPathNode::BottomRight,
+            HexPathNode::BottomLeft,
+            HexPathNode::Left,
+            HexPathNode::TopLeft,
+            HexPathNode::TopRight,
+        ];
+
+        match CLOCKWISE.iter().position(|&node| node == self) {
+            Some(index) => CLOCKWISE[(index + rotation.steps() as usize) % 6],
+            None => self,
+        }
+    }
+}
+
+/// How far the map is turned on screen, in 60 degree steps clockwise
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Rotation(u8);
+
+impl Rotation {
+    pub fn steps(self) -> u8 {
+        self.0
+    }
+
+    /// Turned a further `steps` clockwise, negative steps turn anticlockwise
+    pub fn turned(self, steps: i32) -> Rotation {
+        Rotation((self.0 as i32 + steps).rem_euclid(6) as u8)
+    }
+
+    /// Where `position` on the map ends up on screen
+    pub fn rotate(self, position: Axial) -> Axial {
+        let (mut q, mut r) = (position.q, position.r);
+        for _ in 0..self.0 {
+            let turned = (-r, q + r);
+            q = turned.0;
+            r = turned.1;
+        }
+        Axial::new(q, r)
+    }
+
+    /// Where `position` on screen is on the map
+    pub fn unrotate(self, position: Axial) -> Axial {
+        self.inverse().rotate(position)
+    }
+
+    pub fn rotate_fractional(self, position: FractionalAxial) -> FractionalAxial {
+        let (mut q, mut r) = (position.q, position.r);
+        for _ in 0..self.0 {
+            let turned = (-r, q + r);
+            q = turned.0;
+            r = turned.1;
+        }
+        FractionalAxial { q, r }
+    }
+
+    pub fn unrotate_fractional(self, position: FractionalAxial) -> FractionalAxial {
+        self.inverse().rotate_fractional(position)
+    }
+
+    fn inverse(self) -> Rotation {
+        Rotation((6 - self.0) % 6)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PathError {
+    /// The start or the goal isn't on the map
+    NoTile,
+    /// Nothing connects the start to the goal with the movement profile
+    Unreachable,
+    /// The search gave up after expanding this many tiles
+    TooFar(usize),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathError::NoTile => write!(f, "the start or goal isn't on the map"),
+            PathError::Unreachable => write!(f, "the goal can't be reached"),
+            PathError::TooFar(expanded) => {
+                write!(f, "no path found after searching {} tiles", expanded)
+            }
+        }
+    }
+}
+
+/// What terrain an agent can move over
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct MovementProfile {
+    /// Most levels the agent can step up or down at once
+    pub max_climb: u8,
+    /// Whether the agent can walk over bricks, columns at `MAX_BRICK_HEIGHT` stop everyone
+    pub crosses_bricks: bool,
+}
+
+impl Default for MovementProfile {
+    fn default() -> Self {
+        MovementProfile {
+            max_climb: 1,
+            crosses_bricks: true,
+        }
+    }
+}
+
+/// How an agent gets to the base
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Movement {
+    /// Follows the flow field of the profile
+    Ground(MovementProfile),
+    /// Flies in a straight line at the nearest goal, ignoring the terrain
+    Flying,
+}
+
+/// Handle to a goal set registered with `Map::set_goals`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct GoalSetId(usize);
+
+/// Hexes agents seeking the set walk towards, named so data files can refer to it
+pub struct GoalSet {
+    pub name: String,
+    pub goals: Vec<Hex>,
+}
+
+/// Picks a flow field: the goals an agent seeks and the terrain it can cross on the way
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct FlowKey {
+    pub goals: GoalSetId,
+    pub profile: MovementProfile,
+}
+
+/// Directions towards the goals of one goal set for one movement profile
+pub struct FlowField {
+    pub dijkstra: HexMap<HexPathNode>,
+    /// Weighted distance from every reachable tile to the nearest goal
+    pub distances: HexMap<u32>,
 }
 
 pub struct Map {
     pub terrain: HexMap<HexTileData>,
-    pub dijkstra: HexMap<HexPathNode>,
+    /// Flow fields agents are currently using, built by `require_flow`
+    pub flows: HashMap<FlowKey, FlowField>,
+    /// Flow fields `update_flows` keeps even when no agent is using them
+    pub kept_flows: HashSet<FlowKey>,
+    /// Positions of the chunks that make up the terrain
+    pub chunks: Vec<(i32, i32)>,
+    /// Indexed by `GoalSetId`
+    goal_sets: Vec<GoalSet>,
+    /// Cost function the flow fields are built with
+    pub step_cost: StepCost,
+    /// Height of the lowest tile, like `terrain.tallest` it's only updated when a tile goes past it
+    pub lowest: u8,
+    /// Only changes how the map is drawn and picked with the mouse
+    pub rotation: Rotation,
 }
 
 impl Map {
-    pub fn new() -> Map {
+    pub fn new(generator: &mut dyn TerrainGenerator) -> Map {
+        Map::with_size(generator, WIDTH, HEIGHT)
+    }
+
+    /// Generates a map `width` by `height` chunks large, starting at chunk (-1, -1)
+    pub fn with_size(generator: &mut dyn TerrainGenerator, width: usize, height: usize) -> Map {
+        let mut chunks = vec![];
+        for q in 0..width {
+            for r in 0..height {
+                let (chunk_q, chunk_r) = (q as i32 - 1, r as i32 - 1);
+                chunks.push((chunk_q, chunk_r, generator.generate_chunk(chunk_q, chunk_r)));
+            }
+        }
+
+        Map::from_chunks(chunks)
+    }
+
+    pub fn from_chunks(chunks: Vec<(i32, i32, ChunkTiles)>) -> Map {
         let hex_width = 36.;
         let hex_height = 36.;
         let hex_vert_step = 28.;
@@ -76,58 +253,57 @@ impl Map {
             wall_vert_step,
         );
 
-        let mut rand = StdRng::from_entropy();
-        let mut chunks = vec![];
-        let mut tallest = 0;
-        for q in 0..WIDTH {
-            for r in 0..HEIGHT {
-                let mut tiles = [None; CHUNK_WIDTH * CHUNK_HEIGHT];
-
-                for tile in tiles.iter_mut() {
-                    let value = rand.gen_range(0, MAX_FLOOR_HEIGHT as u16 + 1) as u8;
-                    *tile = Some(HexTileData::new(value));
-                    if value > tallest {
-                        tallest = value;
-                    }
+        let mut chunk_positions = vec![];
+        let (mut lowest, mut tallest) = (u8::MAX, 0);
+        for (chunk_q, chunk_r, tiles) in chunks.into_iter() {
+            for tile in tiles.iter().flatten() {
+                if tile.get_height() > tallest {
+                    tallest = tile.get_height();
+                }
+                if tile.get_height() < lowest {
+                    lowest = tile.get_height();
                 }
-
-                chunks.push(HexChunk::new(tiles, q as i32 - 1, r as i32 - 1));
             }
+
+            chunk_positions.push((chunk_q, chunk_r));
+            terrain.insert_chunk(HexChunk::new(tiles, chunk_q, chunk_r));
         }
 
         terrain.get_height = HexTileData::get_height;
-
         terrain.tallest = tallest;
-        for chunk in chunks.into_iter() {
-            terrain.insert_chunk(chunk);
-        }
 
-        let dijkstra = HexMap::<HexPathNode>::new(
-            hex_width,
-            hex_height,
-            hex_vert_step,
-            hex_depth_step,
-            wall_vert_offset,
-            wall_vert_step,
-        );
-        let goal_hex = Axial::new(10, 5).to_hex();
+        Map {
+            terrain,
+            flows: HashMap::new(),
+            kept_flows: HashSet::new(),
+            chunks: chunk_positions,
+            goal_sets: vec![],
+            step_cost: default_step_cost,
+            lowest: lowest.min(tallest),
+            rotation: Rotation::default(),
+        }
+    }
 
-        let mut map = Map { terrain, dijkstra };
+    /// Reads the tiles of a chunk back out of the terrain
+    pub fn chunk_tiles(&self, chunk_q: i32, chunk_r: i32) -> ChunkTiles {
+        let mut tiles = [None; CHUNK_WIDTH * CHUNK_HEIGHT];
 
-        let goals = vec![goal_hex + Axial::new(0, 1), goal_hex + Axial::new(1, 1)];
-        map.update_dijkstra(goals);
+        for (index, tile) in tiles.iter_mut().enumerate() {
+            let hex = chunk_tile_position(chunk_q, chunk_r, index).to_hex();
+            *tile = self.terrain.get_tile(hex).copied();
+        }
 
-        map
+        tiles
     }
 
-    pub fn get_path(&self, start: Hex) -> Option<Vec<Hex>> {
+    pub fn get_path(&self, start: Hex, key: FlowKey) -> Option<Vec<Hex>> {
         let mut path = vec![start];
 
-        self.dijkstra.get_tile(start)?;
+        self.flow(start, key)?;
 
         let mut current_tile = start;
         loop {
-            let path_node = *self.dijkstra.get_tile(current_tile).unwrap();
+            let path_node = self.flow(current_tile, key).unwrap();
 
             if path_node == HexPathNode::Goal {
                 return Some(path);
@@ -141,11 +317,84 @@ impl Map {
         }
     }
 
+    /// Cheapest path from `start` to `goal` with `profile` using A*, both ends included.
+    /// Steps follow the same cost function as the flow fields, which has to charge at least
+    /// `STEP_COST` a step for the path to be the cheapest. At most `max_expanded` tiles are
+    /// searched when given.
+    pub fn find_path(
+        &self,
+        start: Hex,
+        goal: Hex,
+        profile: MovementProfile,
+        max_expanded: Option<usize>,
+    ) -> Result<Vec<Hex>, PathError> {
+        if self.terrain.get_tile(start).is_none() || self.terrain.get_tile(goal).is_none() {
+            return Err(PathError::NoTile);
+        }
+
+        let heuristic = |hex: Hex| hex_distance(hex.to_axial(), goal.to_axial()) as u32 * STEP_COST;
+
+        let mut costs = HashMap::new();
+        let mut came_from = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        costs.insert(hex_key(start), 0);
+        queue.push(Reverse((heuristic(start), 0, hex_key(start))));
+
+        let mut expanded = 0;
+        while let Some(Reverse((_, cost, key))) = queue.pop() {
+            if key == hex_key(goal) {
+                let mut path = vec![goal];
+                let mut current = key;
+                while let Some(&previous) = came_from.get(&current) {
+                    path.push(key_hex(previous));
+                    current = previous;
+                }
+                path.reverse();
+
+                return Ok(path);
+            }
+            if cost > costs[&key] {
+                continue;
+            }
+
+            if max_expanded.is_some_and(|max| expanded >= max) {
+                return Err(PathError::TooFar(expanded));
+            }
+            expanded += 1;
+
+            let tile = key_hex(key);
+            let tile_data = self.terrain.get_tile(tile).unwrap();
+            for &neighbor in tile.neighbors().iter() {
+                let step = self
+                    .terrain
+                    .get_tile(neighbor)
+                    .and_then(|neighbor_data| (self.step_cost)(tile_data, neighbor_data, profile));
+                if let Some(step) = step {
+                    let through = cost + step;
+                    let neighbor_key = hex_key(neighbor);
+                    if costs
+                        .get(&neighbor_key)
+                        .is_none_or(|&known| through < known)
+                    {
+                        costs.insert(neighbor_key, through);
+                        came_from.insert(neighbor_key, key);
+                        queue.push(Reverse((
+                            through + heuristic(neighbor),
+                            through,
+                            neighbor_key,
+                        )));
+                    }
+                }
+            }
+        }
+
+        Err(PathError::Unreachable)
+    }
+
     pub fn flatten_tile(&mut self, hex: Hex, height: u8) {
         if let Some(tile) = self.terrain.get_tile_mut(hex) {
-            if tile.get_height() == height {
-                return;
-            } else if tile.get_height() < height {
+            if tile.get_height() < height {
                 tile.wall_height = height;
             } else if tile.get_height() > height {
                 tile.wall_height = height;
@@ -156,13 +405,459 @@ impl Map {
             }
         } else {
             self.terrain.set_tile(hex, HexTileData::new_wall(height));
+        }
+    }
+
+    /// Registers the goal set called `name`, or replaces its goals if it already exists and
+    /// rebuilds the flow fields seeking it
+    pub fn set_goals(&mut self, name: &str, goals: Vec<Hex>) -> GoalSetId {
+        let id = match self.goal_set(name) {
+            Some(id) => {
+                self.goal_sets[id.0].goals = goals;
+                id
+            }
+            None => {
+                self.goal_sets.push(GoalSet {
+                    name: name.to_string(),
+                    goals,
+                });
+                GoalSetId(self.goal_sets.len() - 1)
+            }
+        };
+
+        for (key, flow) in self.flows.iter_mut() {
+            if key.goals == id {
+                update_dijkstra_hexmap(
+                    &self.terrain,
+                    &mut flow.dijkstra,
+                    &mut flow.distances,
+                    &self.goal_sets[id.0].goals,
+                    self.step_cost,
+                    key.profile,
+                );
+            }
+        }
+
+        id
+    }
+
+    pub fn goal_set(&self, name: &str) -> Option<GoalSetId> {
+        self.goal_sets
+            .iter()
+            .position(|set| set.name == name)
+            .map(GoalSetId)
+    }
+
+    pub fn goal_set_ids(&self) -> impl Iterator<Item = GoalSetId> {
+        (0..self.goal_sets.len()).map(GoalSetId)
+    }
+
+    pub fn goals(&self, id: GoalSetId) -> &[Hex] {
+        &self.goal_sets[id.0].goals
+    }
+
+    pub fn goal_set_name(&self, id: GoalSetId) -> &str {
+        &self.goal_sets[id.0].name
+    }
+
+    /// Rebuilds every flow field from scratch
+    pub fn refresh_dijkstra(&mut self) {
+        for (key, flow) in self.flows.iter_mut() {
+            update_dijkstra_hexmap(
+                &self.terrain,
+                &mut flow.dijkstra,
+                &mut flow.distances,
+                &self.goal_sets[key.goals.0].goals,
+                self.step_cost,
+                key.profile,
+            );
+        }
+    }
+
+    /// Fixes up the flow fields after the height of a single tile changed, this gives the same
+    /// directions as `refresh_dijkstra` but only touches the tiles whose distances change.
+    pub fn repair_dijkstra(&mut self, changed: Hex) {
+        for (key, flow) in self.flows.iter_mut() {
+            repair_dijkstra_hexmap(
+                &self.terrain,
+                &mut flow.dijkstra,
+                &mut flow.distances,
+                &self.goal_sets[key.goals.0].goals,
+                self.step_cost,
+                key.profile,
+                changed,
+            );
+        }
+    }
+
+    /// Builds the flow field for `key` if there isn't one yet
+    pub fn require_flow(&mut self, key: FlowKey) {
+        if self.flows.contains_key(&key) {
             return;
+        }
+
+        let mut flow = FlowField {
+            dijkstra: empty_like(&self.terrain),
+            distances: empty_like(&self.terrain),
+        };
+
+        update_dijkstra_hexmap(
+            &self.terrain,
+            &mut flow.dijkstra,
+            &mut flow.distances,
+            &self.goal_sets[key.goals.0].goals,
+            self.step_cost,
+            key.profile,
+        );
+        self.flows.insert(key, flow);
+    }
+
+    /// Drops every flow field not in `keys`
+    pub fn retain_flows(&mut self, keys: &HashSet<FlowKey>) {
+        self.flows.retain(|key, _| keys.contains(key));
+    }
+
+    /// Keeps a flow field for every key in `keys` and `kept_flows` and drops the rest
+    pub fn update_flows(&mut self, mut keys: HashSet<FlowKey>) {
+        keys.extend(self.kept_flows.iter().copied());
+
+        self.retain_flows(&keys);
+        for &key in keys.iter() {
+            self.require_flow(key);
+        }
+    }
+
+    /// Where the center of the tile at `position` is drawn, with the map's rotation
+    pub fn axial_to_pixel(&self, position: Axial) -> Vec2<f32> {
+        self.terrain.axial_to_pixel(self.rotation.rotate(position))
+    }
+
+    /// Same as `axial_to_pixel` but for positions between tile centers
+    pub fn fractional_to_pixel(&self, position: FractionalAxial) -> Vec2<f32> {
+        let (origin, q_step, r_step) = self.pixel_basis();
+        let position = self.rotation.rotate_fractional(position);
+
+        origin + q_step * position.q + r_step * position.r
+    }
+
+    /// Inverse of `fractional_to_pixel`, for the ground at height 0
+    pub fn pixel_to_fractional(&self, pixel: Vec2<f32>) -> FractionalAxial {
+        let (origin, q_step, r_step) = self.pixel_basis();
+        let offset = pixel - origin;
+
+        let determinant = q_step.x * r_step.y - r_step.x * q_step.y;
+        let position = FractionalAxial {
+            q: (offset.x * r_step.y - r_step.x * offset.y) / determinant,
+            r: (q_step.x * offset.y - offset.x * q_step.y) / determinant,
+        };
+        self.rotation.unrotate_fractional(position)
+    }
+
+    /// Tile whose top is drawn under `pixel`, taller tiles are picked over the ones they hide
+    pub fn pixel_to_hex(&self, pixel: Vec2<f32>) -> Option<Axial> {
+        (0..=self.terrain.tallest).rev().find_map(|height| {
+            let lowered = pixel + Vec2::new(0., height as f32 * self.terrain.hex_depth_step);
+            let position = round_fractional(self.pixel_to_fractional(lowered));
+            self.terrain
+                .get_tile(position.to_hex())
+                .filter(|tile| tile.get_height() == height)
+                .map(|_| position)
+        })
+    }
+
+    /// Screen position of the unrotated origin and how far one step along q and r moves it
+    fn pixel_basis(&self) -> (Vec2<f32>, Vec2<f32>, Vec2<f32>) {
+        let origin = self.terrain.axial_to_pixel(Axial::new(0, 0));
+        let q_step = self.terrain.axial_to_pixel(Axial::new(1, 0)) - origin;
+        let r_step = self.terrain.axial_to_pixel(Axial::new(0, 1)) - origin;
+
+        (origin, q_step, r_step)
+    }
+
+    /// A tile is flat when it's level with every neighbouring tile
+    pub fn is_flat(&self, hex: Hex) -> bool {
+        let height = if let Some(tile) = self.terrain.get_tile(hex) {
+            tile.get_height()
+        } else {
+            return false;
+        };
+
+        hex.neighbors().iter().all(|&neighbor| {
+            self.terrain
+                .get_tile(neighbor)
+                .is_none_or(|tile| tile.get_height() == height)
+        })
+    }
+
+    /// Direction to walk in from `hex`, `None` if no goal can be reached from it or there's no
+    /// flow field for `key`
+    pub fn flow(&self, hex: Hex, key: FlowKey) -> Option<HexPathNode> {
+        match self.flows.get(&key)?.dijkstra.get_tile(hex) {
+            Some(HexPathNode::Unreachable) | None => None,
+            Some(&node) => Some(node),
+        }
+    }
+
+    /// Weighted distance from `hex` to the nearest goal of the flow field
+    pub fn distance_to_goal(&self, hex: Hex, key: FlowKey) -> Option<u32> {
+        self.flows
+            .get(&key)?
+            .distances
+            .get_tile(hex)
+            .copied()
+            .filter(|&distance| distance != u32::MAX)
+    }
+
+    pub fn is_goal(&self, hex: Hex, goals: GoalSetId) -> bool {
+        self.goals(goals)
+            .iter()
+            .any(|&goal| hex_key(goal) == hex_key(hex))
+    }
+
+    /// Whether `hex` is a goal of any goal set
+    pub fn is_any_goal(&self, hex: Hex) -> bool {
+        self.goal_set_ids().any(|goals| self.is_goal(hex, goals))
+    }
+
+    /// Neighbours of `from` that are on a shortest path to a goal of the flow field, starting
+    /// with the one it points at
+    pub fn shortest_steps(&self, from: Axial, key: FlowKey) -> Vec<Axial> {
+        let hex = from.to_hex();
+        let (tile, distance) = match (self.terrain.get_tile(hex), self.distance_to_goal(hex, key)) {
+            (Some(tile), Some(distance)) => (tile, distance),
+            _ => return vec![],
+        };
+
+        let mut steps = match self.flow(hex, key) {
+            Some(HexPathNode::Goal) | None => return vec![],
+            Some(direction) => vec![(hex + direction.to_hex().to_axial()).to_axial()],
+        };
+
+        for &neighbor in hex.neighbors().iter() {
+            let neighbor_tile = match self.terrain.get_tile(neighbor) {
+                Some(neighbor_tile) => neighbor_tile,
+                None => continue,
+            };
+
+            let cost = (self.step_cost)(tile, neighbor_tile, key.profile);
+            let neighbor_distance = self.distance_to_goal(neighbor, key);
+            if let (Some(cost), Some(neighbor_distance)) = (cost, neighbor_distance) {
+                let step = neighbor.to_axial();
+                if neighbor_distance + cost == distance && hex_distance(step, steps[0]) != 0 {
+                    steps.push(step);
+                }
+            }
+        }
+
+        steps
+    }
+
+    /// The goal of the set closest to `from` in a straight line
+    pub fn nearest_goal(&self, from: Axial, goals: GoalSetId) -> Option<Axial> {
+        self.goals(goals)
+            .iter()
+            .map(|goal| goal.to_axial())
+            .min_by_key(|&goal| hex_distance(from, goal))
+    }
+
+    /// The tiles a flyer at `from` passes over on its way to the nearest goal, starting with
+    /// `from` itself
+    pub fn flight_path(&self, from: Axial, goals: GoalSetId) -> Option<Vec<Axial>> {
+        self.nearest_goal(from, goals)
+            .map(|goal| hex_line(from, goal))
+    }
+
+    /// Height in levels a flyer at `from` cruises at, clear of the tallest tile on its way
+    pub fn flight_altitude(&self, from: Axial, goals: GoalSetId) -> f32 {
+        let tallest = self
+            .flight_path(from, goals)
+            .unwrap_or_else(|| vec![from])
+            .iter()
+            .filter_map(|tile| self.terrain.get_tile(tile.to_hex()))
+            .map(|tile| tile.wall_height)
+            .max()
+            .unwrap_or(0);
+
+        tallest as f32 + FLIGHT_CLEARANCE
+    }
+
+    /// Tiles a goal of the flow field can be reached from, as if the tile at `replaced.0`
+    /// had been replaced by `replaced.1`
+    fn reaching_goal(
+        &self,
+        key: FlowKey,
+        replaced: Option<(Hex, HexTileData)>,
+    ) -> HashSet<(i32, i32)> {
+        let tile_at = |hex: Hex| match replaced {
+            Some((replaced, tile)) if hex_key(replaced) == hex_key(hex) => Some(tile),
+            _ => self.terrain.get_tile(hex).copied(),
         };
+
+        let mut reached = HashSet::new();
+        let mut queue = VecDeque::new();
+
+        for &goal in self.goals(key.goals).iter() {
+            if tile_at(goal).is_some() && reached.insert(hex_key(goal)) {
+                queue.push_back(goal);
+            }
+        }
+
+        while let Some(current) = queue.pop_front() {
+            let current_data = tile_at(current).unwrap();
+            for &neighbor in current.neighbors().iter() {
+                let neighbor_data = match tile_at(neighbor) {
+                    Some(neighbor_data) => neighbor_data,
+                    None => continue,
+                };
+
+                if (self.step_cost)(&neighbor_data, &current_data, key.profile).is_some()
+                    && reached.insert(hex_key(neighbor))
+                {
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        reached
+    }
+
+    /// Returns the first of `sources` that can reach a goal of the flow field now, but
+    /// couldn't if `hex` were replaced by `tile`
+    pub fn cut_off_by(
+        &self,
+        hex: Hex,
+        tile: HexTileData,
+        sources: &[Axial],
+        key: FlowKey,
+    ) -> Option<Axial> {
+        let before = self.reaching_goal(key, None);
+        let after = self.reaching_goal(key, Some((hex, tile)));
+
+        sources
+            .iter()
+            .find(|source| {
+                let key = (source.q, source.r);
+                before.contains(&key) && !after.contains(&key)
+            })
+            .copied()
+    }
+}
+
+/// Tiles whose sprites overlap the area between `top_left` and `bottom_right` in world pixels,
+/// row by row on screen so they're in draw order with the map's rotation. Tile sprites are
+/// `hex_width` by `hex_height` and centered where they're drawn, a column reaches from its top
+/// at `wall_height` down to its wall at height 0.
+pub fn tiles_in_view(map: &Map, top_left: Vec2<f32>, bottom_right: Vec2<f32>) -> Vec<Axial> {
+    let terrain = &map.terrain;
+
+    // How far a column's sprites reach past its hex center, the wall at height 0 below. Rows
+    // are picked as if every column was the tallest then each column is checked on its own.
+    let below = terrain.wall_vert_offset + terrain.hex_height / 2.;
+    let above = |height: u8| height as f32 * terrain.hex_depth_step + terrain.hex_height / 2.;
+    let half_width = terrain.hex_width / 2.;
+
+    // Smallest whole number above `min` and largest below `max`, overlapping edges don't count
+    let range = |min: f32, max: f32| (min.floor() as i32 + 1)..=(max.ceil() as i32 - 1);
+
+    let rows = range(
+        (top_left.y - terrain.position.y - below) / terrain.hex_vert_step,
+        (bottom_right.y - terrain.position.y + above(terrain.tallest)) / terrain.hex_vert_step,
+    );
+
+    let mut tiles = vec![];
+    for r in rows {
+        let row_offset = r as f32 / 2.;
+        let columns = range(
+            (top_left.x - terrain.position.x - half_width) / terrain.hex_width - row_offset,
+            (bottom_right.x - terrain.position.x + half_width) / terrain.hex_width - row_offset,
+        );
+
+        let center_y = terrain.position.y + r as f32 * terrain.hex_vert_step;
+        for q in columns {
+            let axial = map.rotation.unrotate(Axial::new(q, r));
+            match terrain.get_tile(axial.to_hex()) {
+                Some(tile) if center_y - above(tile.wall_height) < bottom_right.y => {
+                    tiles.push(axial)
+                }
+                _ => {}
+            }
+        }
     }
 
-    pub fn update_dijkstra(&mut self, goals: Vec<Hex>) {
-        update_dijkstra_hexmap(&self.terrain, &mut self.dijkstra, goals);
+    tiles
+}
+
+/// An empty hexmap with the same layout as `terrain`
+fn empty_like<T: Copy, U>(terrain: &HexMap<U>) -> HexMap<T> {
+    HexMap::new(
+        terrain.hex_width,
+        terrain.hex_height,
+        terrain.hex_vert_step,
+        terrain.hex_depth_step,
+        terrain.wall_vert_offset,
+        terrain.wall_vert_step,
+    )
+}
+
+pub fn hex_distance(a: Axial, b: Axial) -> i32 {
+    let (q, r) = (a.q - b.q, a.r - b.r);
+    (q.abs() + r.abs() + (q + r).abs()) / 2
+}
+
+pub fn fractional_distance(a: FractionalAxial, b: FractionalAxial) -> f32 {
+    let (q, r) = (a.q - b.q, a.r - b.r);
+    (q.abs() + r.abs() + (q + r).abs()) / 2.
+}
+
+/// Every hex on the straight line from `a` to `b`, both included
+pub fn hex_line(a: Axial, b: Axial) -> Vec<Axial> {
+    let steps = hex_distance(a, b);
+
+    // Nudged off the hex edges so the rounding doesn't flip between neighbours
+    let (from_q, from_r) = (a.q as f32 + 1e-6, a.r as f32 + 1e-6);
+    let (to_q, to_r) = (b.q as f32 + 1e-6, b.r as f32 + 1e-6);
+
+    (0..=steps)
+        .map(|step| {
+            let t = if steps == 0 {
+                0.
+            } else {
+                step as f32 / steps as f32
+            };
+
+            round_fractional(FractionalAxial {
+                q: from_q + (to_q - from_q) * t,
+                r: from_r + (to_r - from_r) * t,
+            })
+        })
+        .collect()
+}
+
+/// The hex a fractional position lies in
+pub fn round_fractional(position: FractionalAxial) -> Axial {
+    let s = -position.q - position.r;
+    let (mut q, mut r, rounded_s) = (position.q.round(), position.r.round(), s.round());
+
+    let (q_diff, r_diff, s_diff) = (
+        (q - position.q).abs(),
+        (r - position.r).abs(),
+        (rounded_s - s).abs(),
+    );
+    if q_diff > r_diff && q_diff > s_diff {
+        q = -r - rounded_s;
+    } else if r_diff > s_diff {
+        r = -q - rounded_s;
     }
+
+    Axial::new(q as i32, r as i32)
+}
+
+/// Axial position of the tile at `index` in a chunk, tiles are laid out row by row
+pub fn chunk_tile_position(chunk_q: i32, chunk_r: i32, index: usize) -> Axial {
+    Axial::new(
+        chunk_q * CHUNK_WIDTH as i32 + (index % CHUNK_WIDTH) as i32,
+        chunk_r * CHUNK_HEIGHT as i32 + (index / CHUNK_WIDTH) as i32,
+    )
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -191,49 +886,688 @@ impl HexTileData {
     }
 }
 
+/// Cost of stepping from one tile onto a neighbouring tile, `None` if the step can't be made
+pub type StepCost = fn(&HexTileData, &HexTileData, MovementProfile) -> Option<u32>;
+
+/// Agents can step up or down as many levels as their profile allows, climbing costs more than
+/// walking down and bricks slow agents down. Columns at `MAX_BRICK_HEIGHT` can't be walked on
+/// at all.
+pub fn default_step_cost(
+    from: &HexTileData,
+    to: &HexTileData,
+    profile: MovementProfile,
+) -> Option<u32> {
+    let (from_height, to_height) = (from.get_height(), to.get_height());
+
+    let (larger, smaller) = if to_height > from_height {
+        (to_height, from_height)
+    } else {
+        (from_height, to_height)
+    };
+
+    if larger - smaller > profile.max_climb || larger >= MAX_BRICK_HEIGHT {
+        return None;
+    }
+    if to.wall_height > to.ground_height && !profile.crosses_bricks {
+        return None;
+    }
+
+    let mut cost = STEP_COST;
+    if to_height > from_height {
+        cost += CLIMB_COST * (to_height - from_height) as u32;
+    }
+    if to.wall_height > to.ground_height {
+        cost += BRICK_COST;
+    }
+
+    Some(cost)
+}
+
+fn hex_key(hex: Hex) -> (i32, i32) {
+    let axial = hex.to_axial();
+    (axial.q, axial.r)
+}
+
+fn key_hex(key: (i32, i32)) -> Hex {
+    Axial::new(key.0, key.1).to_hex()
+}
+
+fn distance_of(distances: &HexMap<u32>, hex: Hex) -> u32 {
+    distances.get_tile(hex).copied().unwrap_or(u32::MAX)
+}
+
+/// Points `tile` at the first neighbour, in a fixed order, that its distance was reached through.
+/// Going by the settled distances means a full rebuild and a repair always agree on ties.
+fn settle_direction(
+    terrain: &HexMap<HexTileData>,
+    dijkstra: &mut HexMap<HexPathNode>,
+    distances: &HexMap<u32>,
+    step_cost: StepCost,
+    profile: MovementProfile,
+    tile: Hex,
+) {
+    let tile_data = if let Some(tile_data) = terrain.get_tile(tile) {
+        tile_data
+    } else {
+        return;
+    };
+
+    let distance = distance_of(distances, tile);
+    if distance == u32::MAX {
+        if dijkstra.get_tile(tile).is_some() {
+            dijkstra.set_tile(tile, HexPathNode::Unreachable);
+        }
+        return;
+    }
+
+    for &neighbor in tile.neighbors().iter() {
+        let neighbor_distance = distance_of(distances, neighbor);
+        if neighbor_distance == u32::MAX {
+            continue;
+        }
+
+        let cost = terrain
+            .get_tile(neighbor)
+            .and_then(|neighbor_data| step_cost(tile_data, neighbor_data, profile));
+        if let Some(cost) = cost {
+            if neighbor_distance + cost == distance {
+                dijkstra.set_tile(tile, HexPathNode::from_hex(neighbor, tile));
+                return;
+            }
+        }
+    }
+}
+
+/// Relaxes tiles outwards from the queue until no distance can be lowered any more,
+/// returns every tile whose distance was lowered.
+fn flood_distances(
+    terrain: &HexMap<HexTileData>,
+    distances: &mut HexMap<u32>,
+    step_cost: StepCost,
+    profile: MovementProfile,
+    mut queue: BinaryHeap<Reverse<(u32, (i32, i32))>>,
+) -> HashSet<(i32, i32)> {
+    let mut lowered = HashSet::new();
+
+    while let Some(Reverse((distance, key))) = queue.pop() {
+        let tile = key_hex(key);
+        if distance > distance_of(distances, tile) {
+            continue;
+        }
+
+        let tile_data = if let Some(tile_data) = terrain.get_tile(tile) {
+            tile_data
+        } else {
+            continue;
+        };
+
+        for &neighbor in tile.neighbors().iter() {
+            let cost = terrain
+                .get_tile(neighbor)
+                .and_then(|neighbor_data| step_cost(neighbor_data, tile_data, profile));
+            if let Some(cost) = cost {
+                let through = distance + cost;
+                if through < distance_of(distances, neighbor) {
+                    distances.set_tile(neighbor, through);
+
+                    let neighbor_key = hex_key(neighbor);
+                    lowered.insert(neighbor_key);
+                    queue.push(Reverse((through, neighbor_key)));
+                }
+            }
+        }
+    }
+
+    lowered
+}
+
 pub fn update_dijkstra_hexmap(
     terrain: &HexMap<HexTileData>,
     dijkstra: &mut HexMap<HexPathNode>,
-    mut goals: Vec<Hex>,
+    distances: &mut HexMap<u32>,
+    goals: &[Hex],
+    step_cost: StepCost,
+    profile: MovementProfile,
 ) {
     dijkstra.clear_map();
+    distances.clear_map();
 
+    let mut queue = BinaryHeap::new();
     for &hex in goals.iter() {
         dijkstra.set_tile(hex, HexPathNode::Goal);
+        distances.set_tile(hex, 0);
+        queue.push(Reverse((0, hex_key(hex))));
+    }
+
+    let reached = flood_distances(terrain, distances, step_cost, profile, queue);
+
+    for &key in reached.iter() {
+        settle_direction(
+            terrain,
+            dijkstra,
+            distances,
+            step_cost,
+            profile,
+            key_hex(key),
+        );
+    }
+}
+
+pub fn repair_dijkstra_hexmap(
+    terrain: &HexMap<HexTileData>,
+    dijkstra: &mut HexMap<HexPathNode>,
+    distances: &mut HexMap<u32>,
+    goals: &[Hex],
+    step_cost: StepCost,
+    profile: MovementProfile,
+    changed: Hex,
+) {
+    let goal_keys: HashSet<_> = goals.iter().map(|&hex| hex_key(hex)).collect();
+
+    // Every tile whose route to a goal passes through the changed tile could now be further away
+    let mut invalidated = HashSet::new();
+    let mut stack = vec![changed];
+    if !goal_keys.contains(&hex_key(changed)) {
+        invalidated.insert(hex_key(changed));
+    }
+    while let Some(tile) = stack.pop() {
+        for &neighbor in tile.neighbors().iter() {
+            let direction = match dijkstra.get_tile(neighbor) {
+                Some(HexPathNode::Goal) | Some(HexPathNode::Unreachable) | None => continue,
+                Some(direction) => direction.to_hex(),
+            };
+
+            let mut next = neighbor;
+            next += direction;
+            if hex_key(next) == hex_key(tile) && invalidated.insert(hex_key(neighbor)) {
+                stack.push(neighbor);
+            }
+        }
+    }
+
+    for &key in invalidated.iter() {
+        distances.set_tile(key_hex(key), u32::MAX);
+    }
+
+    // Seed the invalidated tiles from the neighbours whose distances are still known
+    let mut queue = BinaryHeap::new();
+    for &key in invalidated.iter() {
+        let tile = key_hex(key);
+        let tile_data = if let Some(tile_data) = terrain.get_tile(tile) {
+            tile_data
+        } else {
+            continue;
+        };
+
+        let mut best = u32::MAX;
+        for &neighbor in tile.neighbors().iter() {
+            let neighbor_distance = distance_of(distances, neighbor);
+            if neighbor_distance == u32::MAX {
+                continue;
+            }
+
+            let cost = terrain
+                .get_tile(neighbor)
+                .and_then(|neighbor_data| step_cost(tile_data, neighbor_data, profile));
+            if let Some(cost) = cost {
+                best = best.min(neighbor_distance + cost);
+            }
+        }
+
+        if best != u32::MAX {
+            distances.set_tile(tile, best);
+            queue.push(Reverse((best, key)));
+        }
+    }
+    if goal_keys.contains(&hex_key(changed)) {
+        queue.push(Reverse((0, hex_key(changed))));
+    }
+
+    let lowered = flood_distances(terrain, distances, step_cost, profile, queue);
+
+    // Directions depend on the distances of neighbours and on the steps into the changed tile
+    let mut dirty: HashSet<_> = invalidated.union(&lowered).copied().collect();
+    dirty.insert(hex_key(changed));
+    let neighbors: Vec<_> = dirty
+        .iter()
+        .flat_map(|&key| key_hex(key).neighbors().to_vec())
+        .collect();
+    dirty.extend(neighbors.into_iter().map(hex_key));
+
+    for &key in dirty.iter() {
+        if !goal_keys.contains(&key) {
+            settle_direction(
+                terrain,
+                dijkstra,
+                distances,
+                step_cost,
+                profile,
+                key_hex(key),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::NoiseGenerator;
+
+    /// A 3 by 3 block of chunks with a few holes and heights from 0 to `tallest`
+    fn map(tallest: u8) -> Map {
+        let mut chunks = vec![];
+        for chunk_q in -1..=1 {
+            for chunk_r in -1..=1 {
+                let mut tiles = [None; CHUNK_WIDTH * CHUNK_HEIGHT];
+                for (index, tile) in tiles.iter_mut().enumerate() {
+                    let position = chunk_tile_position(chunk_q, chunk_r, index);
+                    if (position.q * 5 + position.r * 3).rem_euclid(11) == 0 {
+                        continue;
+                    }
+
+                    let height = (position.q * 7 + position.r * 13).rem_euclid(tallest as i32 + 1);
+                    *tile = Some(HexTileData::new(height as u8));
+                }
+                chunks.push((chunk_q, chunk_r, tiles));
+            }
+        }
+
+        Map::from_chunks(chunks)
+    }
+
+    /// Every tile whose sprites overlap the area, projected one at a time
+    fn overlapping(map: &Map, top_left: Vec2<f32>, bottom_right: Vec2<f32>) -> HashSet<(i32, i32)> {
+        let terrain = &map.terrain;
+        let mut tiles = HashSet::new();
+
+        for &(chunk_q, chunk_r) in map.chunks.iter() {
+            for index in 0..CHUNK_WIDTH * CHUNK_HEIGHT {
+                let position = chunk_tile_position(chunk_q, chunk_r, index);
+                let tile = match terrain.get_tile(position.to_hex()) {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+
+                let pixel = map.axial_to_pixel(position);
+                let left = pixel.x - terrain.hex_width / 2.;
+                let right = pixel.x + terrain.hex_width / 2.;
+                let top = pixel.y
+                    - tile.wall_height as f32 * terrain.hex_depth_step
+                    - terrain.hex_height / 2.;
+                let bottom = pixel.y + terrain.wall_vert_offset + terrain.hex_height / 2.;
+
+                if left < bottom_right.x
+                    && right > top_left.x
+                    && top < bottom_right.y
+                    && bottom > top_left.y
+                {
+                    tiles.insert((position.q, position.r));
+                }
+            }
+        }
+
+        tiles
+    }
+
+    /// Flow fields for two movement profiles towards the goals at `goals`, on 2 by 2 chunks of
+    /// noise terrain
+    fn flow_map(goals: &[Axial]) -> (Map, Vec<FlowKey>) {
+        let mut map = Map::with_size(&mut NoiseGenerator::new(MAP_SEED), 2, 2);
+        for &goal in goals.iter() {
+            map.flatten_tile(goal.to_hex(), 1);
+        }
+
+        let goals = map.set_goals(
+            DEFAULT_GOALS,
+            goals.iter().map(|goal| goal.to_hex()).collect(),
+        );
+        let profiles = [
+            MovementProfile::default(),
+            MovementProfile {
+                max_climb: 2,
+                crosses_bricks: false,
+            },
+        ];
+
+        let keys: Vec<_> = profiles
+            .iter()
+            .map(|&profile| FlowKey { goals, profile })
+            .collect();
+        for &key in keys.iter() {
+            map.require_flow(key);
+        }
+
+        (map, keys)
+    }
+
+    /// Name of the edit, the tile it changes and how
+    type TileEdit = (&'static str, Axial, fn(&mut HexTileData));
+
+    fn assert_same_flows(repaired: &Map, rebuilt: &Map, keys: &[FlowKey], edit: &str) {
+        for &(chunk_q, chunk_r) in rebuilt.chunks.iter() {
+            for index in 0..CHUNK_WIDTH * CHUNK_HEIGHT {
+                let hex = chunk_tile_position(chunk_q, chunk_r, index).to_hex();
+                for &key in keys.iter() {
+                    assert_eq!(
+                        repaired.flow(hex, key),
+                        rebuilt.flow(hex, key),
+                        "direction at {:?} after {}",
+                        hex.to_axial(),
+                        edit
+                    );
+                    assert_eq!(
+                        repaired.distance_to_goal(hex, key),
+                        rebuilt.distance_to_goal(hex, key),
+                        "distance at {:?} after {}",
+                        hex.to_axial(),
+                        edit
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn repair_matches_rebuild() {
+        let goals = [Axial::new(0, 0), Axial::new(1, 0)];
+        let (mut repaired, keys) = flow_map(&goals);
+        let (mut rebuilt, _) = flow_map(&goals);
+        assert!(repaired
+            .distance_to_goal(Axial::new(3, 0).to_hex(), keys[0])
+            .is_some());
+
+        let edits: Vec<TileEdit> = vec![
+            ("raising a wall", Axial::new(2, 0), |tile| {
+                tile.wall_height = MAX_BRICK_HEIGHT
+            }),
+            ("raising a ring of walls", Axial::new(1, 1), |tile| {
+                tile.wall_height = MAX_BRICK_HEIGHT
+            }),
+            ("lowering a wall", Axial::new(2, 0), |tile| {
+                tile.wall_height = tile.ground_height
+            }),
+            ("adding bricks", Axial::new(-2, 3), |tile| {
+                tile.wall_height = tile.ground_height + 1
+            }),
+            ("lowering the ground", Axial::new(4, -3), |tile| {
+                tile.ground_height = 0;
+                tile.wall_height = 0;
+            }),
+            ("raising a goal", Axial::new(1, 0), |tile| {
+                tile.ground_height = 2;
+                tile.wall_height = 2;
+            }),
+        ];
+
+        for (edit, position, change) in edits {
+            let hex = position.to_hex();
+            change(repaired.terrain.get_tile_mut(hex).unwrap());
+            change(rebuilt.terrain.get_tile_mut(hex).unwrap());
+
+            repaired.repair_dijkstra(hex);
+            rebuilt.refresh_dijkstra();
+            assert_same_flows(&repaired, &rebuilt, &keys, edit);
+        }
+
+        // Changing the goals rebuilds their flow fields, later repairs have to start from the
+        // new goals
+        let changes = [
+            (
+                "adding a goal",
+                vec![goals[0], goals[1], Axial::new(-8, -8)],
+            ),
+            ("removing a goal", vec![Axial::new(-8, -8)]),
+        ];
+        for (edit, goals) in changes.iter() {
+            let goals: Vec<_> = goals.iter().map(|goal| goal.to_hex()).collect();
+            repaired.set_goals(DEFAULT_GOALS, goals.clone());
+            rebuilt.set_goals(DEFAULT_GOALS, goals);
+            assert_same_flows(&repaired, &rebuilt, &keys, edit);
+
+            let hex = Axial::new(-7, -8).to_hex();
+            for map in [&mut repaired, &mut rebuilt].iter_mut() {
+                let tile = map.terrain.get_tile_mut(hex).unwrap();
+                tile.wall_height = if tile.wall_height == MAX_BRICK_HEIGHT {
+                    tile.ground_height
+                } else {
+                    MAX_BRICK_HEIGHT
+                };
+            }
+            repaired.repair_dijkstra(hex);
+            rebuilt.refresh_dijkstra();
+            assert_same_flows(&repaired, &rebuilt, &keys, edit);
+        }
+    }
+
+    #[test]
+    fn step_costs() {
+        let profile = MovementProfile::default();
+        let (low, high, cliff) = (
+            HexTileData::new(0),
+            HexTileData::new(1),
+            HexTileData::new(2),
+        );
+
+        assert_eq!(default_step_cost(&low, &low, profile), Some(STEP_COST));
+        assert_eq!(default_step_cost(&high, &high, profile), Some(STEP_COST));
+        assert_eq!(
+            default_step_cost(&low, &high, profile),
+            Some(STEP_COST + CLIMB_COST)
+        );
+        // Walking down is as cheap as walking on the flat
+        assert_eq!(default_step_cost(&high, &low, profile), Some(STEP_COST));
+
+        // Two levels is more than the default profile can climb or drop
+        assert_eq!(default_step_cost(&low, &cliff, profile), None);
+        assert_eq!(default_step_cost(&cliff, &low, profile), None);
+
+        let climber = MovementProfile {
+            max_climb: 2,
+            ..profile
+        };
+        assert_eq!(
+            default_step_cost(&low, &cliff, climber),
+            Some(STEP_COST + 2 * CLIMB_COST)
+        );
+        assert_eq!(default_step_cost(&cliff, &low, climber), Some(STEP_COST));
+
+        // Nobody walks on or off the tallest columns
+        let top = HexTileData::new(MAX_BRICK_HEIGHT);
+        let below_top = HexTileData::new(MAX_BRICK_HEIGHT - 1);
+        assert_eq!(default_step_cost(&below_top, &top, climber), None);
+        assert_eq!(default_step_cost(&top, &below_top, climber), None);
+        assert_eq!(default_step_cost(&top, &top, climber), None);
+    }
+
+    /// One flat chunk with `ring` around the tile at (8, 8)
+    fn ringed_map(ring: HexTileData) -> Map {
+        let tiles = [Some(HexTileData::new(0)); CHUNK_WIDTH * CHUNK_HEIGHT];
+        let mut map = Map::from_chunks(vec![(0, 0, tiles)]);
+        for &neighbor in Axial::new(8, 8).to_hex().neighbors().iter() {
+            map.terrain.set_tile(neighbor, ring);
+        }
+
+        map
+    }
+
+    /// The path from (2, 3) to (8, 8) as axial coordinates
+    fn path(
+        map: &Map,
+        profile: MovementProfile,
+        max_expanded: Option<usize>,
+    ) -> Result<Vec<Axial>, PathError> {
+        let (start, goal) = (Axial::new(2, 3).to_hex(), Axial::new(8, 8).to_hex());
+        map.find_path(start, goal, profile, max_expanded)
+            .map(|path| path.iter().map(|hex| hex.to_axial()).collect())
+    }
+
+    #[test]
+    fn find_path_rules() {
+        let (start, goal) = (Axial::new(2, 3), Axial::new(8, 8));
+        let profile = MovementProfile::default();
+        let climber = MovementProfile {
+            max_climb: 2,
+            ..profile
+        };
+        let no_bricks = MovementProfile {
+            crosses_bricks: false,
+            ..profile
+        };
+
+        let map = ringed_map(HexTileData::new(0));
+        let found = path(&map, profile, None).unwrap();
+        assert_eq!(found.first(), Some(&start));
+        assert_eq!(found.last(), Some(&goal));
+        assert_eq!(found.len(), hex_distance(start, goal) as usize + 1);
+        for step in found.windows(2) {
+            assert_eq!(hex_distance(step[0], step[1]), 1);
+        }
+
+        let off_map = Axial::new(-1, 0).to_hex();
+        assert_eq!(
+            map.find_path(start.to_hex(), off_map, profile, None).err(),
+            Some(PathError::NoTile)
+        );
+
+        // The search gives up after expanding the limit without reaching the goal
+        assert_eq!(path(&map, profile, Some(3)), Err(PathError::TooFar(3)));
+        let limit = Some(CHUNK_WIDTH * CHUNK_HEIGHT);
+        assert_eq!(path(&map, profile, limit), Ok(found));
+
+        // Ground two levels up can only be climbed with a `max_climb` of 2
+        let map = ringed_map(HexTileData::new(2));
+        assert_eq!(path(&map, profile, None), Err(PathError::Unreachable));
+        assert_eq!(path(&map, climber, None).unwrap().last(), Some(&goal));
+
+        // A level of bricks stops the profiles that can't cross bricks
+        let mut bricks = HexTileData::new(0);
+        bricks.wall_height = 1;
+        let map = ringed_map(bricks);
+        assert_eq!(path(&map, profile, None).unwrap().last(), Some(&goal));
+        assert_eq!(path(&map, no_bricks, None), Err(PathError::Unreachable));
     }
 
-    while !goals.is_empty() {
-        let length = goals.len();
-        for _ in 0..length {
-            let tile = *goals.first().unwrap();
-            let neighbors: Vec<_> = tile
-                .neighbors()
-                .iter()
-                .filter(|&&hex| {
-                    if terrain.get_tile(hex).is_some() && dijkstra.get_tile(hex).is_none() {
-                        let tile_height = terrain.get_tile(tile).unwrap().get_height();
-                        let hex_height = terrain.get_tile(hex).unwrap().get_height();
-
-                        let (larger, smaller) = if hex_height > tile_height {
-                            (hex_height, tile_height)
-                        } else {
-                            (tile_height, hex_height)
-                        };
-
-                        larger - smaller <= 1 && larger < MAX_BRICK_HEIGHT
-                    } else {
-                        false
+    #[test]
+    fn rotation_round_trips() {
+        let positions = [
+            Axial::new(0, 0),
+            Axial::new(3, -1),
+            Axial::new(-7, 12),
+            Axial::new(25, 40),
+        ];
+
+        assert_eq!(Rotation::default().turned(6), Rotation::default());
+        assert_eq!(
+            Rotation::default().turned(-1),
+            Rotation::default().turned(5)
+        );
+
+        for &position in positions.iter() {
+            // Turning one step at a time for a full turn ends up back where it started
+            let mut turned = position;
+            for _ in 0..6 {
+                turned = Rotation::default().turned(1).rotate(turned);
+            }
+            assert_eq!(turned, position);
+            assert_eq!(Rotation::default().turned(6).rotate(position), position);
+
+            for steps in 0..6 {
+                let rotation = Rotation::default().turned(steps);
+                assert_eq!(rotation.unrotate(rotation.rotate(position)), position);
+                assert_eq!(rotation.rotate(rotation.unrotate(position)), position);
+
+                let fractional = FractionalAxial {
+                    q: position.q as f32 + 0.25,
+                    r: position.r as f32 - 0.5,
+                };
+                let back = rotation.unrotate_fractional(rotation.rotate_fractional(fractional));
+                assert!((back.q - fractional.q).abs() < 1e-4);
+                assert!((back.r - fractional.r).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn picking_round_trips_at_every_rotation() {
+        let mut map = map(0);
+        let raised = Axial::new(4, 4);
+        map.terrain
+            .get_tile_mut(raised.to_hex())
+            .unwrap()
+            .wall_height = 3;
+        map.terrain.tallest = 3;
+
+        for steps in 0..6 {
+            map.rotation = Rotation::default().turned(steps);
+
+            for &(chunk_q, chunk_r) in map.chunks.iter() {
+                for index in 0..CHUNK_WIDTH * CHUNK_HEIGHT {
+                    let position = chunk_tile_position(chunk_q, chunk_r, index);
+                    let tile = match map.terrain.get_tile(position.to_hex()) {
+                        Some(tile) => *tile,
+                        None => continue,
+                    };
+
+                    // The middle of the tile's top, taller tiles in front can cover it
+                    let mut pixel = map.axial_to_pixel(position);
+                    pixel.y -= tile.wall_height as f32 * map.terrain.hex_depth_step;
+                    let picked = map.pixel_to_hex(pixel);
+                    if picked == Some(raised) && position != raised {
+                        continue;
                     }
-                })
-                .cloned()
-                .collect();
+                    assert_eq!(picked, Some(position), "rotation {}", steps);
 
-            for neighbor in neighbors {
-                goals.push(neighbor);
-                dijkstra.set_tile(neighbor, HexPathNode::from_hex(tile, neighbor));
+                    let fractional = map.pixel_to_fractional(map.axial_to_pixel(position));
+                    assert!((fractional.q - position.q as f32).abs() < 1e-3);
+                    assert!((fractional.r - position.r as f32).abs() < 1e-3);
+                }
             }
+        }
+    }
+
+    #[test]
+    fn tiles_in_view_matches_sprites() {
+        let views = [
+            ((-100., -80.), (140., 60.)),
+            // Edges exactly on tile centers
+            ((-18., -14.), (162., 98.)),
+            ((0., -600.), (1., 900.)),
+            ((-2000., 50.), (2000., 60.)),
+            ((-300., -500.), (200., -350.)),
+            ((-5000., -5000.), (5000., 5000.)),
+            ((10000., 10000.), (10100., 10100.)),
+        ];
+
+        for &tallest in [0, 3, 9].iter() {
+            let mut map = map(tallest);
+            assert_eq!(map.terrain.tallest, tallest);
 
-            goals.remove(0);
+            for steps in 0..6 {
+                map.rotation = Rotation::default().turned(steps);
+
+                for &((left, top), (right, bottom)) in views.iter() {
+                    let (top_left, bottom_right) = (Vec2::new(left, top), Vec2::new(right, bottom));
+                    let tiles = tiles_in_view(&map, top_left, bottom_right);
+
+                    let found: HashSet<_> = tiles.iter().map(|tile| (tile.q, tile.r)).collect();
+                    assert_eq!(found.len(), tiles.len(), "a tile was returned twice");
+                    assert_eq!(
+                        found,
+                        overlapping(&map, top_left, bottom_right),
+                        "tallest {}, rotation {}, view {:?} to {:?}",
+                        tallest,
+                        steps,
+                        top_left,
+                        bottom_right
+                    );
+
+                    // Back to front on screen
+                    for pair in tiles.windows(2) {
+                        let (a, b) = (map.rotation.rotate(pair[0]), map.rotation.rotate(pair[1]));
+                        assert!((a.r, a.q) < (b.r, b.q));
+                    }
+                }
+            }
         }
     }
 }
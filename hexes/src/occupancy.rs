@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use vermarine_lib::{hexmap::Axial, shipyard::EntityId};
+
+#[derive(Copy, Clone, Debug)]
+struct Occupant {
+    entity: EntityId,
+    flying: bool,
+}
+
+/// Agents indexed by the hex they're standing on, or moving onto while between two hexes.
+/// Flyers and walkers fill up a hex separately.
+///
+/// Rebuilt at the start of every tick by `update_occupancy` and kept up to date by
+/// `move_agents`, agents removed later in the tick stay in it until the next rebuild.
+pub struct Occupancy {
+    /// Walkers, or flyers, allowed on a hex at once
+    pub capacity: usize,
+    tiles: HashMap<(i32, i32), Vec<Occupant>>,
+}
+
+impl Occupancy {
+    pub fn new(capacity: usize) -> Self {
+        Occupancy {
+            capacity,
+            tiles: HashMap::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+    }
+
+    pub fn insert(&mut self, entity: EntityId, hex: Axial, flying: bool) {
+        self.tiles
+            .entry((hex.q, hex.r))
+            .or_default()
+            .push(Occupant { entity, flying });
+    }
+
+    pub fn remove(&mut self, entity: EntityId, hex: Axial) {
+        if let Some(occupants) = self.tiles.get_mut(&(hex.q, hex.r)) {
+            occupants.retain(|occupant| occupant.entity != entity);
+            if occupants.is_empty() {
+                self.tiles.remove(&(hex.q, hex.r));
+            }
+        }
+    }
+
+    /// Whether another walker, or flyer, can fit on `hex`
+    pub fn is_full(&self, hex: Axial, flying: bool) -> bool {
+        let count = self.tiles.get(&(hex.q, hex.r)).map_or(0, |occupants| {
+            occupants
+                .iter()
+                .filter(|occupant| occupant.flying == flying)
+                .count()
+        });
+
+        count >= self.capacity
+    }
+
+    /// Agents on `hex`, walkers and flyers
+    pub fn at(&self, hex: Axial) -> impl Iterator<Item = EntityId> + '_ {
+        self.tiles
+            .get(&(hex.q, hex.r))
+            .into_iter()
+            .flatten()
+            .map(|occupant| occupant.entity)
+    }
+
+    /// Agents within `radius` hexes of `center`, nearer rings aren't guaranteed to come first
+    pub fn within(&self, center: Axial, radius: u32) -> Vec<EntityId> {
+        let radius = radius as i32;
+
+        let mut found = vec![];
+        for q in -radius..=radius {
+            for r in (-radius).max(-q - radius)..=radius.min(-q + radius) {
+                found.extend(self.at(Axial::new(center.q + q, center.r + r)));
+            }
+        }
+
+        found
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::HashSet;
+
+use vermarine_lib::{
+    hexmap::{Axial, CHUNK_HEIGHT, CHUNK_WIDTH},
+    rendering::{
+        draw_buffer::{DrawBuffer, DrawCommand},
+        Drawables,
+    },
+    tetra::{
+        graphics::{Color, Rectangle},
+        input::{self, InputContext, Key},
+        math::{Vec2, Vec3},
+    },
+};
+
+use crate::{
+    components::{Agent, Transform},
+    consts::*,
+    map::{FlowKey, HexPathNode, Map, MovementProfile},
+};
+
+/// Everything an overlay layer can draw from, built once per frame while any layer is enabled
+pub struct OverlayContext<'a> {
+    pub map: &'a Map,
+    pub drawables: &'a Drawables,
+    /// Tiles around the camera
+    pub tiles: Vec<Axial>,
+    pub agents: Vec<(&'a Agent, &'a Transform)>,
+}
+
+pub type DrawOverlay = fn(&OverlayContext, &mut DrawBuffer);
+
+pub struct OverlayLayer {
+    /// Key that switches the layer on and off
+    pub key: Key,
+    pub enabled: bool,
+    /// Whether the layer draws the default flow field
+    pub uses_flow: bool,
+    pub draw: DrawOverlay,
+}
+
+/// Debug layers drawn on top of the terrain by `draw_overlays`, in the order they were registered
+pub struct DebugOverlays {
+    layers: Vec<OverlayLayer>,
+}
+
+impl DebugOverlays {
+    pub fn new() -> Self {
+        DebugOverlays { layers: vec![] }
+    }
+
+    pub fn register(&mut self, key: Key, draw: DrawOverlay) {
+        self.layers.push(OverlayLayer {
+            key,
+            enabled: false,
+            uses_flow: false,
+            draw,
+        });
+    }
+
+    /// Registers a layer that draws the default flow field, which is kept while it's enabled
+    pub fn register_flow(&mut self, key: Key, draw: DrawOverlay) {
+        self.register(key, draw);
+        self.layers.last_mut().unwrap().uses_flow = true;
+    }
+
+    /// Switches the layers whose keys were pressed this frame
+    pub fn toggle_pressed(&mut self, input_ctx: &InputContext) {
+        for layer in self.layers.iter_mut() {
+            if input::is_key_pressed(input_ctx, layer.key) {
+                layer.enabled = !layer.enabled;
+            }
+        }
+    }
+
+    pub fn any_enabled(&self) -> bool {
+        self.layers.iter().any(|layer| layer.enabled)
+    }
+
+    pub fn enabled(&self) -> impl Iterator<Item = &OverlayLayer> {
+        self.layers.iter().filter(|layer| layer.enabled)
+    }
+
+    /// Flow fields the enabled layers draw, which have to exist even with no agents using them
+    pub fn flow_keys(&self, map: &Map) -> HashSet<FlowKey> {
+        if self.enabled().any(|layer| layer.uses_flow) {
+            default_flow_key(map).into_iter().collect()
+        } else {
+            HashSet::new()
+        }
+    }
+}
+
+impl Default for DebugOverlays {
+    fn default() -> Self {
+        let mut overlays = DebugOverlays::new();
+        overlays.register(Key::F1, draw_dots);
+        overlays.register_flow(Key::F2, draw_flow);
+        overlays.register_flow(Key::F3, draw_distances);
+        overlays.register(Key::F4, draw_agent_paths);
+        overlays.register(Key::F7, draw_chunks);
+        overlays.register(Key::F8, draw_coordinates);
+        overlays
+    }
+}
+
+/// Flow field of the default goals and movement profile
+fn default_flow_key(map: &Map) -> Option<FlowKey> {
+    map.goal_set(DEFAULT_GOALS).map(|goals| FlowKey {
+        goals,
+        profile: MovementProfile::default(),
+    })
+}
+
+/// Pixel position of the top of a tile, for drawing without `draw_iso`
+fn tile_top(map: &Map, tile: Axial) -> Option<Vec2<f32>> {
+    let height = map.terrain.get_tile(tile.to_hex())?.wall_height;
+    let mut position = map.axial_to_pixel(tile);
+    position.y -= height as f32 * map.terrain.hex_depth_step;
+    Some(position)
+}
+
+fn draw_marker(
+    draw_buffer: &mut DrawBuffer,
+    marker_tex: u64,
+    map: &Map,
+    tile: Axial,
+    color: Color,
+) {
+    let pos = map.axial_to_pixel(tile);
+    let height = match map.terrain.get_tile(tile.to_hex()) {
+        Some(tile) => tile.wall_height,
+        None => return,
+    };
+
+    draw_buffer.draw(
+        DrawCommand::new(marker_tex)
+            .position(Vec3::new(
+                pos.x,
+                pos.y,
+                height as f32 * map.terrain.hex_depth_step,
+            ))
+            .draw_iso(true)
+            .color(color),
+    );
+}
+
+pub fn draw_arrow(
+    draw_buffer: &mut DrawBuffer,
+    arrow_sheet: u64,
+    map: &Map,
+    key: FlowKey,
+    tile: Axial,
+) {
+    let pos = map.axial_to_pixel(tile);
+
+    let (terrain_tile, flow_tile) = if let (Some(terrain_tile), Some(flow_tile)) = (
+        map.terrain.get_tile(tile.to_hex()),
+        map.flow(tile.to_hex(), key),
+    ) {
+        (terrain_tile, flow_tile)
+    } else {
+        return;
+    };
+
+    draw_buffer.draw(
+        DrawCommand::new(arrow_sheet)
+            .position(Vec3::new(
+                pos.x,
+                pos.y,
+                terrain_tile.wall_height as f32 * map.terrain.hex_depth_step,
+            ))
+            .draw_iso(true)
+            .clip(match flow_tile.rotated(map.rotation) {
+                HexPathNode::TopLeft => Rectangle::row(0., 0., 36., 36.).next().unwrap(),
+                HexPathNode::TopRight => Rectangle::row(0., 0., 36., 36.).nth(1).unwrap(),
+                HexPathNode::BottomRight => Rectangle::row(0., 0., 36., 36.).nth(2).unwrap(),
+                HexPathNode::BottomLeft => Rectangle::row(0., 0., 36., 36.).nth(3).unwrap(),
+                HexPathNode::Right => Rectangle::row(0., 0., 36., 36.).nth(4).unwrap(),
+                HexPathNode::Left => Rectangle::row(0., 0., 36., 36.).nth(5).unwrap(),
+                _ => return,
+            }),
+    );
+}
+
+/// Draws `text` centered on `position` using the digits sheet, characters other than
+/// digits, `-` and `,` are skipped
+pub fn draw_text(draw_buffer: &mut DrawBuffer, digits_tex: u64, text: &str, position: Vec2<f32>) {
+    let width = (text.len().max(1) - 1) as f32 * DIGIT_ADVANCE + DIGIT_WIDTH;
+    let mut x = position.x - width / 2.;
+    let y = position.y - DIGIT_HEIGHT / 2.;
+
+    for character in text.chars() {
+        let index = match character {
+            '0'..='9' => character as u32 - '0' as u32,
+            '-' => 10,
+            ',' => 11,
+            _ => {
+                x += DIGIT_ADVANCE;
+                continue;
+            }
+        };
+
+        draw_buffer.draw(
+            DrawCommand::new(digits_tex)
+                .position(Vec3::new(x, y, 0.))
+                .clip(Rectangle::new(
+                    index as f32 * DIGIT_ADVANCE,
+                    0.,
+                    DIGIT_WIDTH,
+                    DIGIT_HEIGHT,
+                )),
+        );
+        x += DIGIT_ADVANCE;
+    }
+}
+
+fn draw_dots(context: &OverlayContext, draw_buffer: &mut DrawBuffer) {
+    let marker_tex = context.drawables.alias[textures::MARKER];
+    for &tile in context.tiles.iter() {
+        draw_marker(draw_buffer, marker_tex, context.map, tile, Color::WHITE);
+    }
+}
+
+fn draw_flow(context: &OverlayContext, draw_buffer: &mut DrawBuffer) {
+    let key = match default_flow_key(context.map) {
+        Some(key) => key,
+        None => return,
+    };
+
+    let arrow_sheet = context.drawables.alias[textures::ARROW_SHEET];
+    for &tile in context.tiles.iter() {
+        draw_arrow(draw_buffer, arrow_sheet, context.map, key, tile);
+    }
+}
+
+/// Distance to the nearest goal over each tile
+fn draw_distances(context: &OverlayContext, draw_buffer: &mut DrawBuffer) {
+    let key = match default_flow_key(context.map) {
+        Some(key) => key,
+        None => return,
+    };
+
+    let digits_tex = context.drawables.alias[textures::DIGITS];
+    for &tile in context.tiles.iter() {
+        let distance = context.map.distance_to_goal(tile.to_hex(), key);
+        if let (Some(distance), Some(position)) = (distance, tile_top(context.map, tile)) {
+            draw_text(draw_buffer, digits_tex, &distance.to_string(), position);
+        }
+    }
+}
+
+/// Arrows along the path every walking agent is following
+fn draw_agent_paths(context: &OverlayContext, draw_buffer: &mut DrawBuffer) {
+    let arrow_sheet = context.drawables.alias[textures::ARROW_SHEET];
+    for (agent, transform) in context.agents.iter() {
+        if let Some(key) = agent.flow_key() {
+            if let Some(path) = context.map.get_path(transform.position.to_hex(), key) {
+                for step in path {
+                    draw_arrow(draw_buffer, arrow_sheet, context.map, key, step.to_axial());
+                }
+            }
+        }
+    }
+}
+
+/// Marks the tiles along the top and left edges of every chunk
+fn draw_chunks(context: &OverlayContext, draw_buffer: &mut DrawBuffer) {
+    let marker_tex = context.drawables.alias[textures::MARKER];
+    for &tile in context.tiles.iter() {
+        let edge = tile.q.rem_euclid(CHUNK_WIDTH as i32) == 0
+            || tile.r.rem_euclid(CHUNK_HEIGHT as i32) == 0;
+        if edge {
+            draw_marker(
+                draw_buffer,
+                marker_tex,
+                context.map,
+                tile,
+                Color::rgb(0.9, 0.2, 0.2),
+            );
+        }
+    }
+}
+
+/// Axial coordinates of each tile, q above r
+fn draw_coordinates(context: &OverlayContext, draw_buffer: &mut DrawBuffer) {
+    let digits_tex = context.drawables.alias[textures::DIGITS];
+    for &tile in context.tiles.iter() {
+        if let Some(position) = tile_top(context.map, tile) {
+            let offset = Vec2::new(0., DIGIT_HEIGHT / 2. + 1.);
+            draw_text(
+                draw_buffer,
+                digits_tex,
+                &tile.q.to_string(),
+                position - offset,
+            );
+            draw_text(
+                draw_buffer,
+                digits_tex,
+                &tile.r.to_string(),
+                position + offset,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::HexTileData;
+
+    #[test]
+    fn enabled_flow_layers_keep_their_flow() {
+        let tiles = [Some(HexTileData::new(0)); CHUNK_WIDTH * CHUNK_HEIGHT];
+        let mut map = Map::from_chunks(vec![(0, 0, tiles)]);
+        map.set_goals(DEFAULT_GOALS, vec![Axial::new(15, 15).to_hex()]);
+        let key = default_flow_key(&map).unwrap();
+
+        let mut overlays = DebugOverlays::default();
+        assert!(overlays.flow_keys(&map).is_empty());
+        for layer in overlays.layers.iter_mut() {
+            layer.enabled = layer.key == Key::F2;
+        }
+        map.kept_flows = overlays.flow_keys(&map);
+
+        // No agents are alive to use the flow field
+        map.update_flows(HashSet::new());
+        assert!(map.flows.contains_key(&key));
+        assert_eq!(
+            map.distance_to_goal(Axial::new(15, 14).to_hex(), key),
+            Some(STEP_COST)
+        );
+
+        map.kept_flows.clear();
+        map.update_flows(HashSet::new());
+        assert!(map.flows.is_empty());
+    }
+}
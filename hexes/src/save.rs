@@ -0,0 +1,792 @@
+use std::{convert::TryInto, error::Error, fmt, fs, io, path::Path};
+
+use vermarine_lib::{
+    hexmap::{Axial, CHUNK_HEIGHT, CHUNK_WIDTH},
+    shipyard::*,
+};
+
+use crate::{
+    components::{Base, Spawner, Transform},
+    consts::*,
+    entity_creator,
+    generation::ChunkTiles,
+    map::{HexTileData, Map},
+};
+
+pub const SAVE_VERSION: u32 = 3;
+/// Version 1 nests had a spawn period instead of an id, they're numbered in order on load
+const NEST_PERIOD_VERSION: u32 = 1;
+/// Saves before version 3 had at most one base, it gets the default goal set on load
+const NAMED_BASES_VERSION: u32 = 3;
+
+const TEXT_MAGIC: &str = "hexes-map";
+const BINARY_MAGIC: &[u8; 4] = b"HXMP";
+
+const CHUNK_TILES: usize = CHUNK_WIDTH * CHUNK_HEIGHT;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SaveFormat {
+    Text,
+    Binary,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file doesn't start with either the text or the binary header
+    UnknownFormat,
+    UnknownVersion(u32),
+    /// A chunk is missing tiles or has a tile that can't exist
+    CorruptChunk {
+        q: i32,
+        r: i32,
+    },
+    /// A line of a text save couldn't be parsed
+    Malformed {
+        line: usize,
+    },
+    /// The goal set name of a base isn't a single word of valid text
+    BadName,
+    UnexpectedEnd,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "could not read save: {}", err),
+            LoadError::UnknownFormat => write!(f, "not a map save"),
+            LoadError::UnknownVersion(version) => {
+                write!(f, "unknown save version {}", version)
+            }
+            LoadError::CorruptChunk { q, r } => write!(f, "chunk ({}, {}) is corrupt", q, r),
+            LoadError::Malformed { line } => write!(f, "malformed save on line {}", line),
+            LoadError::BadName => write!(f, "a base has a malformed name"),
+            LoadError::UnexpectedEnd => write!(f, "save ended unexpectedly"),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    /// A base's goal set name is empty or contains whitespace, the text format couldn't read
+    /// it back
+    BadName(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "could not write save: {}", err),
+            SaveError::BadName(name) => write!(f, "base name \"{}\" isn't a single word", name),
+        }
+    }
+}
+
+impl Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+pub struct ChunkData {
+    pub q: i32,
+    pub r: i32,
+    pub tiles: ChunkTiles,
+}
+
+#[derive(Clone, Debug)]
+pub struct BaseData {
+    pub position: Axial,
+    /// Name of the base's goal set, a single word
+    pub goals: String,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct NestData {
+    pub position: Axial,
+    pub id: u32,
+}
+
+/// Everything needed to rebuild a level: the terrain plus the bases and nests on it
+pub struct SaveData {
+    pub chunks: Vec<ChunkData>,
+    pub bases: Vec<BaseData>,
+    pub nests: Vec<NestData>,
+}
+
+impl SaveData {
+    pub fn capture(world: &World) -> SaveData {
+        world.run(
+            |map: UniqueView<Map>,
+             transforms: View<Transform>,
+             bases: View<Base>,
+             spawners: View<Spawner>| {
+                let chunks = map
+                    .chunks
+                    .iter()
+                    .map(|&(q, r)| ChunkData {
+                        q,
+                        r,
+                        tiles: map.chunk_tiles(q, r),
+                    })
+                    .collect();
+
+                let mut bases_data = vec![];
+                for (transform, base) in (&transforms, &bases).iter() {
+                    bases_data.push(BaseData {
+                        position: transform.position,
+                        goals: map.goal_set_name(base.goals).to_string(),
+                    });
+                }
+
+                let mut nests = vec![];
+                for (transform, spawner) in (&transforms, &spawners).iter() {
+                    nests.push(NestData {
+                        position: transform.position,
+                        id: spawner.id,
+                    });
+                }
+
+                SaveData {
+                    chunks,
+                    bases: bases_data,
+                    nests,
+                }
+            },
+        )
+    }
+
+    /// Builds the terrain, the goal sets are added once the bases are spawned
+    pub fn build_map(&self) -> Map {
+        Map::from_chunks(
+            self.chunks
+                .iter()
+                .map(|chunk| (chunk.q, chunk.r, chunk.tiles))
+                .collect(),
+        )
+    }
+
+    pub fn spawn_entities(&self, world: &mut AllStoragesViewMut) {
+        for base in self.bases.iter() {
+            entity_creator::spawn_base(base.position, &base.goals, world);
+        }
+
+        for nest in self.nests.iter() {
+            entity_creator::create_nest(nest.position, nest.id, world);
+        }
+    }
+
+    pub fn save_file<P: AsRef<Path>>(&self, path: P, format: SaveFormat) -> Result<(), SaveError> {
+        let bytes = match format {
+            SaveFormat::Text => self.to_text()?.into_bytes(),
+            SaveFormat::Binary => self.to_binary()?,
+        };
+        fs::write(path, bytes)?;
+
+        Ok(())
+    }
+
+    /// Both formats only hold names the text format can read back
+    fn check_names(&self) -> Result<(), SaveError> {
+        match self.bases.iter().find(|base| !valid_name(&base.goals)) {
+            Some(base) => Err(SaveError::BadName(base.goals.clone())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<SaveData, LoadError> {
+        SaveData::from_bytes(&fs::read(path)?)
+    }
+
+    /// Reads either format, telling them apart by their header
+    pub fn from_bytes(bytes: &[u8]) -> Result<SaveData, LoadError> {
+        if bytes.starts_with(BINARY_MAGIC) {
+            SaveData::from_binary(bytes)
+        } else if bytes.starts_with(TEXT_MAGIC.as_bytes()) {
+            let text = std::str::from_utf8(bytes).map_err(|_| LoadError::UnknownFormat)?;
+            SaveData::from_text(text)
+        } else {
+            Err(LoadError::UnknownFormat)
+        }
+    }
+
+    pub fn to_text(&self) -> Result<String, SaveError> {
+        self.check_names()?;
+
+        let mut text = format!("{} {}\n", TEXT_MAGIC, SAVE_VERSION);
+
+        for chunk in self.chunks.iter() {
+            text.push_str(&format!("chunk {} {}\n", chunk.q, chunk.r));
+
+            for row in chunk.tiles.chunks(CHUNK_WIDTH) {
+                let row: Vec<_> = row
+                    .iter()
+                    .map(|tile| match tile {
+                        Some(tile) => format!("{}/{}", tile.ground_height, tile.wall_height),
+                        None => "-".to_string(),
+                    })
+                    .collect();
+                text.push_str(&row.join(" "));
+                text.push('\n');
+            }
+        }
+
+        for base in self.bases.iter() {
+            text.push_str(&format!(
+                "base {} {} {}\n",
+                base.position.q, base.position.r, base.goals
+            ));
+        }
+
+        for nest in self.nests.iter() {
+            text.push_str(&format!(
+                "nest {} {} {}\n",
+                nest.position.q, nest.position.r, nest.id
+            ));
+        }
+
+        Ok(text)
+    }
+
+    pub fn from_text(text: &str) -> Result<SaveData, LoadError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty());
+
+        let (line, header) = lines.next().ok_or(LoadError::UnexpectedEnd)?;
+        let mut header = header.split_whitespace();
+        if header.next() != Some(TEXT_MAGIC) {
+            return Err(LoadError::UnknownFormat);
+        }
+        let version = parse(header.next(), line)?;
+        if !(NEST_PERIOD_VERSION..=SAVE_VERSION).contains(&version) {
+            return Err(LoadError::UnknownVersion(version));
+        }
+
+        let mut save = SaveData {
+            chunks: vec![],
+            bases: vec![],
+            nests: vec![],
+        };
+
+        while let Some((line, contents)) = lines.next() {
+            let mut words = contents.split_whitespace();
+
+            match words.next() {
+                Some("chunk") => {
+                    let (q, r) = (parse(words.next(), line)?, parse(words.next(), line)?);
+
+                    let mut tiles = [None; CHUNK_TILES];
+                    for row in tiles.chunks_mut(CHUNK_WIDTH) {
+                        let (_, contents) = lines.next().ok_or(LoadError::UnexpectedEnd)?;
+                        let words: Vec<_> = contents.split_whitespace().collect();
+                        if words.len() != CHUNK_WIDTH {
+                            return Err(LoadError::CorruptChunk { q, r });
+                        }
+
+                        for (tile, word) in row.iter_mut().zip(words) {
+                            *tile = parse_tile(word).ok_or(LoadError::CorruptChunk { q, r })?;
+                        }
+                    }
+
+                    save.chunks.push(ChunkData { q, r, tiles });
+                }
+                Some("base") => {
+                    let position =
+                        Axial::new(parse(words.next(), line)?, parse(words.next(), line)?);
+                    let goals = if version < NAMED_BASES_VERSION {
+                        DEFAULT_GOALS
+                    } else {
+                        words.next().ok_or(LoadError::Malformed { line })?
+                    };
+
+                    save.bases.push(BaseData {
+                        position,
+                        goals: goals.to_string(),
+                    });
+                }
+                Some("nest") => {
+                    let position =
+                        Axial::new(parse(words.next(), line)?, parse(words.next(), line)?);
+                    let id = match version {
+                        NEST_PERIOD_VERSION => {
+                            parse::<u8>(words.next(), line)?;
+                            save.nests.len() as u32
+                        }
+                        _ => parse(words.next(), line)?,
+                    };
+
+                    save.nests.push(NestData { position, id });
+                }
+                _ => return Err(LoadError::Malformed { line }),
+            }
+        }
+
+        Ok(save)
+    }
+
+    pub fn to_binary(&self) -> Result<Vec<u8>, SaveError> {
+        self.check_names()?;
+
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend(&SAVE_VERSION.to_le_bytes());
+
+        bytes.extend(&(self.chunks.len() as u32).to_le_bytes());
+        for chunk in self.chunks.iter() {
+            bytes.extend(&chunk.q.to_le_bytes());
+            bytes.extend(&chunk.r.to_le_bytes());
+
+            for tile in chunk.tiles.iter() {
+                match tile {
+                    Some(tile) => bytes.extend(&[tile.ground_height, tile.wall_height]),
+                    None => bytes.extend(&[u8::MAX, u8::MAX]),
+                }
+            }
+        }
+
+        bytes.extend(&(self.bases.len() as u32).to_le_bytes());
+        for base in self.bases.iter() {
+            bytes.extend(&base.position.q.to_le_bytes());
+            bytes.extend(&base.position.r.to_le_bytes());
+            bytes.extend(&(base.goals.len() as u32).to_le_bytes());
+            bytes.extend(base.goals.as_bytes());
+        }
+
+        bytes.extend(&(self.nests.len() as u32).to_le_bytes());
+        for nest in self.nests.iter() {
+            bytes.extend(&nest.position.q.to_le_bytes());
+            bytes.extend(&nest.position.r.to_le_bytes());
+            bytes.extend(&nest.id.to_le_bytes());
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn from_binary(bytes: &[u8]) -> Result<SaveData, LoadError> {
+        let mut reader = ByteReader { bytes };
+
+        if reader.take(BINARY_MAGIC.len())? != BINARY_MAGIC {
+            return Err(LoadError::UnknownFormat);
+        }
+        let version = reader.u32()?;
+        if !(NEST_PERIOD_VERSION..=SAVE_VERSION).contains(&version) {
+            return Err(LoadError::UnknownVersion(version));
+        }
+
+        let mut chunks = vec![];
+        for _ in 0..reader.u32()? {
+            let (q, r) = (reader.i32()?, reader.i32()?);
+
+            let data = reader
+                .take(CHUNK_TILES * 2)
+                .map_err(|_| LoadError::CorruptChunk { q, r })?;
+
+            let mut tiles = [None; CHUNK_TILES];
+            for (tile, data) in tiles.iter_mut().zip(data.chunks(2)) {
+                *tile = match (data[0], data[1]) {
+                    (u8::MAX, u8::MAX) => None,
+                    (ground_height, wall_height) => Some(
+                        valid_tile(ground_height, wall_height)
+                            .ok_or(LoadError::CorruptChunk { q, r })?,
+                    ),
+                };
+            }
+
+            chunks.push(ChunkData { q, r, tiles });
+        }
+
+        let mut bases = vec![];
+        if version < NAMED_BASES_VERSION {
+            if reader.u8()? != 0 {
+                bases.push(BaseData {
+                    position: Axial::new(reader.i32()?, reader.i32()?),
+                    goals: DEFAULT_GOALS.to_string(),
+                });
+            }
+        } else {
+            for _ in 0..reader.u32()? {
+                let position = Axial::new(reader.i32()?, reader.i32()?);
+                let length = reader.u32()? as usize;
+                let goals = std::str::from_utf8(reader.take(length)?)
+                    .ok()
+                    .filter(|goals| valid_name(goals))
+                    .ok_or(LoadError::BadName)?
+                    .to_string();
+
+                bases.push(BaseData { position, goals });
+            }
+        }
+
+        let mut nests = vec![];
+        for index in 0..reader.u32()? {
+            let position = Axial::new(reader.i32()?, reader.i32()?);
+            let id = match version {
+                NEST_PERIOD_VERSION => {
+                    reader.u8()?;
+                    index
+                }
+                _ => reader.u32()?,
+            };
+
+            nests.push(NestData { position, id });
+        }
+
+        Ok(SaveData {
+            chunks,
+            bases,
+            nests,
+        })
+    }
+}
+
+/// Goal set names are saved as a single word
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(char::is_whitespace)
+}
+
+fn parse<T: std::str::FromStr>(word: Option<&str>, line: usize) -> Result<T, LoadError> {
+    word.and_then(|word| word.parse().ok())
+        .ok_or(LoadError::Malformed { line })
+}
+
+/// Parses a `ground/wall` pair, or `-` for a missing tile
+fn parse_tile(word: &str) -> Option<Option<HexTileData>> {
+    if word == "-" {
+        return Some(None);
+    }
+
+    let mut heights = word.split('/');
+    let ground_height = heights.next()?.parse().ok()?;
+    let wall_height = heights.next()?.parse().ok()?;
+    if heights.next().is_some() {
+        return None;
+    }
+
+    valid_tile(ground_height, wall_height).map(Some)
+}
+
+fn valid_tile(ground_height: u8, wall_height: u8) -> Option<HexTileData> {
+    if ground_height <= wall_height && wall_height <= MAX_BRICK_HEIGHT {
+        Some(HexTileData {
+            ground_height,
+            wall_height,
+        })
+    } else {
+        None
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], LoadError> {
+        if self.bytes.len() < count {
+            return Err(LoadError::UnexpectedEnd);
+        }
+
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, LoadError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> SaveData {
+        let mut tiles = [None; CHUNK_TILES];
+        for (index, tile) in tiles.iter_mut().enumerate().skip(1) {
+            let ground_height = (index % 3) as u8;
+            *tile = Some(HexTileData {
+                ground_height,
+                wall_height: ground_height + (index % 2) as u8,
+            });
+        }
+
+        SaveData {
+            chunks: vec![
+                ChunkData { q: -1, r: 0, tiles },
+                ChunkData {
+                    q: 0,
+                    r: 0,
+                    tiles: [Some(HexTileData::new(1)); CHUNK_TILES],
+                },
+            ],
+            bases: vec![
+                BaseData {
+                    position: Axial::new(10, 5),
+                    goals: "base".to_string(),
+                },
+                BaseData {
+                    position: Axial::new(-3, 2),
+                    goals: "outpost".to_string(),
+                },
+            ],
+            nests: vec![
+                NestData {
+                    position: Axial::new(-12, -7),
+                    id: 0,
+                },
+                NestData {
+                    position: Axial::new(4, -9),
+                    id: 7,
+                },
+            ],
+        }
+    }
+
+    /// Ground and wall height of every tile of a chunk, `None` for holes
+    type ChunkHeights = (i32, i32, Vec<Option<(u8, u8)>>);
+
+    fn chunk_heights(save: &SaveData) -> Vec<ChunkHeights> {
+        save.chunks
+            .iter()
+            .map(|chunk| {
+                let tiles = chunk
+                    .tiles
+                    .iter()
+                    .map(|tile| tile.map(|tile| (tile.ground_height, tile.wall_height)))
+                    .collect();
+                (chunk.q, chunk.r, tiles)
+            })
+            .collect()
+    }
+
+    fn bases(save: &SaveData) -> Vec<(i32, i32, String)> {
+        save.bases
+            .iter()
+            .map(|base| (base.position.q, base.position.r, base.goals.clone()))
+            .collect()
+    }
+
+    fn nests(save: &SaveData) -> Vec<(i32, i32, u32)> {
+        save.nests
+            .iter()
+            .map(|nest| (nest.position.q, nest.position.r, nest.id))
+            .collect()
+    }
+
+    fn assert_same(loaded: &SaveData, saved: &SaveData) {
+        assert_eq!(chunk_heights(loaded), chunk_heights(saved));
+        assert_eq!(bases(loaded), bases(saved));
+        assert_eq!(nests(loaded), nests(saved));
+    }
+
+    /// A chunk of flat tiles at height 1 in the text format
+    fn text_chunk(q: i32, r: i32) -> String {
+        let row = vec!["1/1"; CHUNK_WIDTH].join(" ");
+        let mut text = format!("chunk {} {}\n", q, r);
+        for _ in 0..CHUNK_HEIGHT {
+            text.push_str(&row);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Binary save of a single flat chunk at (0, 0), up to where the bases start
+    fn binary_start(version: u32) -> Vec<u8> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend(&version.to_le_bytes());
+        bytes.extend(&1u32.to_le_bytes());
+        bytes.extend(&0i32.to_le_bytes());
+        bytes.extend(&0i32.to_le_bytes());
+        for _ in 0..CHUNK_TILES {
+            bytes.extend(&[1, 1]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let save = sample();
+        let loaded = SaveData::from_bytes(save.to_text().unwrap().as_bytes()).unwrap();
+        assert_same(&loaded, &save);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let save = sample();
+        let loaded = SaveData::from_bytes(&save.to_binary().unwrap()).unwrap();
+        assert_same(&loaded, &save);
+    }
+
+    #[test]
+    fn unknown_version() {
+        let text = format!("{} {}\n", TEXT_MAGIC, SAVE_VERSION + 1);
+        match SaveData::from_text(&text) {
+            Err(LoadError::UnknownVersion(version)) => assert_eq!(version, SAVE_VERSION + 1),
+            other => panic!("expected an unknown version, got {:?}", other.err()),
+        }
+
+        match SaveData::from_binary(&binary_start(0)) {
+            Err(LoadError::UnknownVersion(0)) => {}
+            other => panic!("expected an unknown version, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert!(matches!(
+            SaveData::from_bytes(b"not a save"),
+            Err(LoadError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn corrupt_chunk() {
+        // Walls can't be lower than the ground
+        let text = format!("{} {}\n", TEXT_MAGIC, SAVE_VERSION)
+            + &text_chunk(2, -1).replacen("1/1", "2/1", 1);
+        assert!(matches!(
+            SaveData::from_text(&text),
+            Err(LoadError::CorruptChunk { q: 2, r: -1 })
+        ));
+
+        // A row one tile short
+        let text = format!("{} {}\n", TEXT_MAGIC, SAVE_VERSION)
+            + &text_chunk(0, 3).replacen("1/1 ", "", 1);
+        assert!(matches!(
+            SaveData::from_text(&text),
+            Err(LoadError::CorruptChunk { q: 0, r: 3 })
+        ));
+
+        let mut bytes = sample().to_binary().unwrap();
+        let first_tile = BINARY_MAGIC.len() + 4 + 4 + 8;
+        bytes[first_tile + 2] = MAX_BRICK_HEIGHT + 1;
+        bytes[first_tile + 3] = MAX_BRICK_HEIGHT + 1;
+        assert!(matches!(
+            SaveData::from_binary(&bytes),
+            Err(LoadError::CorruptChunk { q: -1, r: 0 })
+        ));
+
+        // Cut off partway through the tiles
+        let bytes = &sample().to_binary().unwrap()[..first_tile + 10];
+        assert!(matches!(
+            SaveData::from_binary(bytes),
+            Err(LoadError::CorruptChunk { q: -1, r: 0 })
+        ));
+    }
+
+    #[test]
+    fn unexpected_end() {
+        // Stops after a few rows of the chunk
+        let rows: Vec<_> = text_chunk(0, 0)
+            .lines()
+            .take(4)
+            .map(str::to_string)
+            .collect();
+        let text = format!("{} {}\n{}\n", TEXT_MAGIC, SAVE_VERSION, rows.join("\n"));
+        assert!(matches!(
+            SaveData::from_text(&text),
+            Err(LoadError::UnexpectedEnd)
+        ));
+
+        let bytes = sample().to_binary().unwrap();
+        assert!(matches!(
+            SaveData::from_binary(&bytes[..bytes.len() - 1]),
+            Err(LoadError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            SaveData::from_binary(&bytes[..6]),
+            Err(LoadError::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn bad_names() {
+        for name in &["", "two words", "tab\tname"] {
+            let mut save = sample();
+            save.bases[1].goals = name.to_string();
+
+            match save.to_text() {
+                Err(SaveError::BadName(bad)) => assert_eq!(&bad, name),
+                other => panic!("expected a bad name, got {:?}", other.ok()),
+            }
+            assert!(matches!(save.to_binary(), Err(SaveError::BadName(_))));
+
+            let mut bytes = binary_start(SAVE_VERSION);
+            bytes.extend(&1u32.to_le_bytes());
+            bytes.extend(&0i32.to_le_bytes());
+            bytes.extend(&0i32.to_le_bytes());
+            bytes.extend(&(name.len() as u32).to_le_bytes());
+            bytes.extend(name.as_bytes());
+            bytes.extend(&0u32.to_le_bytes());
+            assert!(matches!(
+                SaveData::from_binary(&bytes),
+                Err(LoadError::BadName)
+            ));
+        }
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let text = format!("{} 1\n", TEXT_MAGIC)
+            + &text_chunk(0, 0)
+            + "base 3 4\nnest 1 2 90\nnest 5 6 45\n";
+        let save = SaveData::from_text(&text).unwrap();
+        assert_eq!(bases(&save), vec![(3, 4, DEFAULT_GOALS.to_string())]);
+        assert_eq!(nests(&save), vec![(1, 2, 0), (5, 6, 1)]);
+
+        let mut bytes = binary_start(1);
+        bytes.push(1);
+        bytes.extend(&3i32.to_le_bytes());
+        bytes.extend(&4i32.to_le_bytes());
+        bytes.extend(&2u32.to_le_bytes());
+        for &(q, r, period) in [(1i32, 2i32, 90u8), (5, 6, 45)].iter() {
+            bytes.extend(&q.to_le_bytes());
+            bytes.extend(&r.to_le_bytes());
+            bytes.push(period);
+        }
+        let save = SaveData::from_binary(&bytes).unwrap();
+        assert_eq!(bases(&save), vec![(3, 4, DEFAULT_GOALS.to_string())]);
+        assert_eq!(nests(&save), vec![(1, 2, 0), (5, 6, 1)]);
+    }
+
+    #[test]
+    fn migrates_version_2() {
+        let text = format!("{} 2\n", TEXT_MAGIC) + &text_chunk(0, 0) + "base 3 4\nnest 1 2 9\n";
+        let save = SaveData::from_text(&text).unwrap();
+        assert_eq!(chunk_heights(&save).len(), 1);
+        assert_eq!(bases(&save), vec![(3, 4, DEFAULT_GOALS.to_string())]);
+        assert_eq!(nests(&save), vec![(1, 2, 9)]);
+
+        // Without a base
+        let mut bytes = binary_start(2);
+        bytes.push(0);
+        bytes.extend(&1u32.to_le_bytes());
+        bytes.extend(&1i32.to_le_bytes());
+        bytes.extend(&2i32.to_le_bytes());
+        bytes.extend(&9u32.to_le_bytes());
+        let save = SaveData::from_binary(&bytes).unwrap();
+        assert!(save.bases.is_empty());
+        assert_eq!(nests(&save), vec![(1, 2, 9)]);
+    }
+}
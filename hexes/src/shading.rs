@@ -0,0 +1,47 @@
+use crate::{consts::*, map::Map};
+
+/// How brightly everything drawn at each height level is lit, from where the level sits in
+/// the map's height range and a light that turns along with the map
+#[derive(Copy, Clone, Debug)]
+pub struct Shading {
+    lowest: u8,
+    tallest: u8,
+    /// Multiplier for walls, from how squarely the walls facing the camera face the light
+    wall_light: f32,
+}
+
+impl Shading {
+    pub fn new(map: &Map) -> Self {
+        // Pointy topped hexes show the walls facing the bottom right and bottom left, 60 and
+        // 120 degrees clockwise from the right of the screen
+        let turned = map.rotation.steps() as f32 * 60.;
+        let facing_light = [60f32, 120.]
+            .iter()
+            .map(|facing| (facing - turned - LIGHT_ANGLE).to_radians().cos().max(0.))
+            .sum::<f32>()
+            / 2.;
+
+        Shading {
+            lowest: map.lowest.min(map.terrain.tallest),
+            tallest: map.terrain.tallest,
+            wall_light: WALL_AMBIENT + (1. - WALL_AMBIENT) * facing_light,
+        }
+    }
+
+    /// Tile tops and whatever is standing on them, heights outside the map's range are
+    /// clamped to it
+    pub fn top(&self, height: u8) -> f32 {
+        if self.tallest == self.lowest {
+            return TINT_TALLEST;
+        }
+
+        let height = height.max(self.lowest).min(self.tallest);
+        let fraction = (height - self.lowest) as f32 / (self.tallest - self.lowest) as f32;
+        TINT_LOWEST + (TINT_TALLEST - TINT_LOWEST) * fraction
+    }
+
+    /// Side walls of the columns
+    pub fn wall(&self, height: u8) -> f32 {
+        self.top(height) * self.wall_light
+    }
+}
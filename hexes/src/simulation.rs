@@ -0,0 +1,497 @@
+use vermarine_lib::{hexmap::Axial, shipyard::*};
+
+use crate::{
+    archetypes::Archetypes,
+    components::{Agent, Base, DamageQueue, Deaths, Health, Ledger},
+    consts::*,
+    data::DataError,
+    entity_creator,
+    generation::NoiseGenerator,
+    map::Map,
+    occupancy::Occupancy,
+    save::SaveData,
+    systems,
+    waves::{WaveDirector, WaveSchedule},
+};
+
+/// Fixed timestep bookkeeping, kept as a unique so systems and rendering can read it
+pub struct Clock {
+    pub tick_rate: f64,
+    /// Time passed that hasn't been simulated yet
+    pub accumulator: f64,
+    pub ticks: u64,
+}
+
+impl Clock {
+    pub fn new(tick_rate: f64) -> Self {
+        Clock {
+            tick_rate,
+            accumulator: 0.,
+            ticks: 0,
+        }
+    }
+
+    pub fn tick_length(&self) -> f64 {
+        1. / self.tick_rate
+    }
+
+    /// How far between the previous and the current tick rendering is
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.tick_length()).min(1.) as f32
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GameState {
+    Playing,
+    /// The base ran out of health, no more agents are spawned
+    Defeat,
+}
+
+/// The game world without any window or renderer attached, advanced one tick at a time.
+/// The windowed game adds its input, camera and drawing uniques on top of this world.
+pub struct Simulation {
+    pub world: World,
+}
+
+impl Simulation {
+    /// The map's goal sets are only complete once the bases are spawned, so the waves are
+    /// checked against them by the constructors that spawn them
+    fn new(map: Map, archetypes: Archetypes, waves: WaveSchedule) -> Self {
+        let world = World::new();
+        world.add_unique(map);
+        world.add_unique(Clock::new(TICKS_PER_SECOND));
+        world.add_unique(DamageQueue::default());
+        world.add_unique(Deaths::default());
+        world.add_unique(Ledger::default());
+        world.add_unique(Occupancy::new(TILE_CAPACITY));
+        world.add_unique(GameState::Playing);
+        world.add_unique(archetypes);
+        world.add_unique(WaveDirector::new(waves));
+
+        Simulation { world }
+    }
+
+    /// The default level, a base in the middle surrounded by nests numbered 0 to 9
+    pub fn scenario(
+        seed: u64,
+        archetypes: Archetypes,
+        waves: WaveSchedule,
+    ) -> Result<Self, DataError> {
+        let map = Map::new(&mut NoiseGenerator::new(seed));
+        let simulation = Simulation::new(map, archetypes, waves);
+
+        simulation.world.run(|mut all_storages| {
+            entity_creator::create_base(Axial::new(10, 5), DEFAULT_GOALS, &mut all_storages);
+
+            entity_creator::create_nest(Axial::new(-5, -7), 0, &mut all_storages);
+            entity_creator::create_nest(Axial::new(12, -15), 1, &mut all_storages);
+            entity_creator::create_nest(Axial::new(-12, -5), 2, &mut all_storages);
+            entity_creator::create_nest(Axial::new(2, -8), 3, &mut all_storages);
+            entity_creator::create_nest(Axial::new(-8, 6), 4, &mut all_storages);
+            entity_creator::create_nest(Axial::new(-5, -15), 5, &mut all_storages);
+            entity_creator::create_nest(Axial::new(11, 14), 6, &mut all_storages);
+            entity_creator::create_nest(Axial::new(5, 13), 7, &mut all_storages);
+            entity_creator::create_nest(Axial::new(2, 4), 8, &mut all_storages);
+            entity_creator::create_nest(Axial::new(14, -3), 9, &mut all_storages);
+        });
+
+        simulation.check_waves()?;
+        Ok(simulation)
+    }
+
+    pub fn from_save(
+        save: &SaveData,
+        archetypes: Archetypes,
+        waves: WaveSchedule,
+    ) -> Result<Self, DataError> {
+        let simulation = Simulation::new(save.build_map(), archetypes, waves);
+        simulation
+            .world
+            .run(|mut all_storages| save.spawn_entities(&mut all_storages));
+
+        simulation.check_waves()?;
+        Ok(simulation)
+    }
+
+    fn check_waves(&self) -> Result<(), DataError> {
+        self.world
+            .run(|map: UniqueView<Map>, director: UniqueView<WaveDirector>| {
+                director.schedule.check_goals(&map)
+            })
+    }
+
+    pub fn tick(&mut self) {
+        self.world
+            .run(|mut deaths: UniqueViewMut<Deaths>| deaths.events.clear());
+
+        self.world.run(systems::store_previous_transforms);
+        self.world.run(systems::update_flow_fields);
+        self.world.run(systems::update_occupancy);
+        self.world.run(systems::move_agents);
+        self.world.run(systems::agents_reach_base);
+        self.world.run(systems::fire_towers);
+        self.world.run(systems::move_projectiles);
+        self.world.run(systems::apply_damage);
+        self.world.run(systems::check_defeat);
+        self.world.run(systems::remove_dead);
+
+        if self.state() == GameState::Playing {
+            self.world.run(systems::direct_waves);
+            self.world.run(systems::collect_income);
+        }
+
+        self.world
+            .run(|mut clock: UniqueViewMut<Clock>| clock.ticks += 1);
+    }
+
+    /// Runs as many ticks as fit in the time passed since the last call, the remainder
+    /// carries over to the next call. Returns how many ticks were run.
+    pub fn advance(&mut self, delta: f64) -> u32 {
+        let tick_length = self.world.run(|mut clock: UniqueViewMut<Clock>| {
+            clock.accumulator += delta;
+            clock.tick_length()
+        });
+
+        let mut ticks = 0;
+        while self.world.run(|clock: UniqueView<Clock>| clock.accumulator) >= tick_length {
+            if ticks == MAX_TICKS_PER_UPDATE {
+                // Too far behind to catch up, drop the time instead of slowing down further
+                self.world.run(|mut clock: UniqueViewMut<Clock>| {
+                    clock.accumulator %= tick_length;
+                });
+                break;
+            }
+
+            self.tick();
+            self.world
+                .run(|mut clock: UniqueViewMut<Clock>| clock.accumulator -= tick_length);
+            ticks += 1;
+        }
+
+        ticks
+    }
+
+    pub fn state(&self) -> GameState {
+        self.world.run(|state: UniqueView<GameState>| *state)
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.world.run(|clock: UniqueView<Clock>| clock.ticks)
+    }
+
+    pub fn wave(&self) -> u32 {
+        self.world
+            .run(|director: UniqueView<WaveDirector>| director.current_wave())
+    }
+
+    /// `None` while the current wave is still spawning
+    pub fn ticks_to_next_wave(&self) -> Option<u32> {
+        self.world
+            .run(|director: UniqueView<WaveDirector>| director.ticks_to_next_wave())
+    }
+
+    /// Health left across every base
+    pub fn base_health(&self) -> u32 {
+        self.world.run(|bases: View<Base>, healths: View<Health>| {
+            let mut total = 0;
+            for (_, health) in (&bases, &healths).iter() {
+                total += health.current;
+            }
+            total
+        })
+    }
+
+    pub fn agent_count(&self) -> usize {
+        self.world.run(|agents: View<Agent>| {
+            let mut count = 0;
+            for _ in agents.iter() {
+                count += 1;
+            }
+            count
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vermarine_lib::hexmap::{FractionalAxial, CHUNK_HEIGHT, CHUNK_WIDTH};
+
+    use super::*;
+    use crate::{
+        components::{Impact, Projectile, ProjectileTarget},
+        edits::{self, EditError, TerrainEdit},
+        map::HexTileData,
+        waves::ScalingCurve,
+    };
+
+    fn scenario() -> Simulation {
+        let archetypes = Archetypes::builtin();
+        let waves = WaveSchedule::builtin(&archetypes).unwrap();
+        Simulation::scenario(MAP_SEED, archetypes, waves).unwrap()
+    }
+
+    /// One flat chunk without any waves, agents head for its far corner
+    fn flat() -> Simulation {
+        let tiles = [Some(HexTileData::new(0)); CHUNK_WIDTH * CHUNK_HEIGHT];
+        let waves = WaveSchedule {
+            scaling: ScalingCurve::Constant,
+            waves: vec![],
+        };
+        let simulation = Simulation::new(
+            Map::from_chunks(vec![(0, 0, tiles)]),
+            Archetypes::builtin(),
+            waves,
+        );
+
+        simulation.world.run(|mut map: UniqueViewMut<Map>| {
+            map.set_goals(DEFAULT_GOALS, vec![Axial::new(15, 15).to_hex()]);
+        });
+
+        simulation
+    }
+
+    fn spawn_grunt(simulation: &Simulation, position: Axial) -> EntityId {
+        simulation
+            .world
+            .run(|mut all_storages: AllStoragesViewMut| {
+                let (archetype, goals) =
+                    all_storages.run(|archetypes: UniqueView<Archetypes>, map: UniqueView<Map>| {
+                        let grunt = archetypes.find("grunt").unwrap();
+                        (
+                            archetypes.list[grunt].clone(),
+                            map.goal_set(DEFAULT_GOALS).unwrap(),
+                        )
+                    });
+                entity_creator::create_agent(position, &archetype, goals, 1., &mut all_storages)
+            })
+    }
+
+    fn fire(
+        simulation: &Simulation,
+        origin: Axial,
+        target: ProjectileTarget,
+        at: Axial,
+        impact: Impact,
+    ) {
+        let destination = FractionalAxial {
+            q: at.q as f32,
+            r: at.r as f32,
+        };
+        simulation
+            .world
+            .run(|mut all_storages: AllStoragesViewMut| {
+                entity_creator::create_projectile(
+                    origin,
+                    target,
+                    destination,
+                    3,
+                    impact,
+                    &mut all_storages,
+                );
+            });
+    }
+
+    /// Flies every projectile until it lands or is blocked, without moving any agents
+    fn land_projectiles(simulation: &Simulation) {
+        for _ in 0..TICKS_PER_SECOND as u32 * 5 {
+            simulation.world.run(systems::move_projectiles);
+            simulation.world.run(systems::apply_damage);
+        }
+
+        let in_flight = simulation.world.run(|projectiles: View<Projectile>| {
+            let mut count = 0;
+            for _ in projectiles.iter() {
+                count += 1;
+            }
+            count
+        });
+        assert_eq!(in_flight, 0);
+    }
+
+    fn damage_taken(simulation: &Simulation, entity: EntityId) -> u32 {
+        simulation.world.run(|healths: View<Health>| {
+            let health = (&healths).get(entity).unwrap();
+            health.max - health.current
+        })
+    }
+
+    #[test]
+    fn terrain_blocks_projectiles() {
+        let simulation = flat();
+        let (origin, target) = (Axial::new(2, 4), Axial::new(12, 4));
+        let grunt = spawn_grunt(&simulation, target);
+
+        fire(
+            &simulation,
+            origin,
+            ProjectileTarget::Entity(grunt),
+            target,
+            Impact::Single,
+        );
+        land_projectiles(&simulation);
+        assert_eq!(damage_taken(&simulation, grunt), 3);
+
+        // A wall halfway that's taller than the top of the arc
+        simulation.world.run(|mut map: UniqueViewMut<Map>| {
+            for r in 3..=5 {
+                let tile = map.terrain.get_tile_mut(Axial::new(7, r).to_hex()).unwrap();
+                tile.wall_height = 8;
+            }
+        });
+
+        fire(
+            &simulation,
+            origin,
+            ProjectileTarget::Entity(grunt),
+            target,
+            Impact::Single,
+        );
+        land_projectiles(&simulation);
+        assert_eq!(damage_taken(&simulation, grunt), 3);
+    }
+
+    #[test]
+    fn splash_damages_everything_in_radius() {
+        let simulation = flat();
+        let landing = Axial::new(8, 8);
+
+        let inside: Vec<_> = [
+            landing,
+            Axial::new(9, 8),
+            Axial::new(8, 9),
+            Axial::new(7, 9),
+        ]
+        .iter()
+        .map(|&position| spawn_grunt(&simulation, position))
+        .collect();
+        let outside: Vec<_> = [Axial::new(10, 8), Axial::new(6, 8), Axial::new(8, 10)]
+            .iter()
+            .map(|&position| spawn_grunt(&simulation, position))
+            .collect();
+
+        fire(
+            &simulation,
+            Axial::new(2, 8),
+            ProjectileTarget::Hex(landing),
+            landing,
+            Impact::Splash { radius: 1 },
+        );
+        land_projectiles(&simulation);
+
+        for &grunt in inside.iter() {
+            assert_eq!(damage_taken(&simulation, grunt), 3);
+        }
+        for &grunt in outside.iter() {
+            assert_eq!(damage_taken(&simulation, grunt), 0);
+        }
+    }
+
+    #[test]
+    fn headless_scenario() {
+        let mut simulation = scenario();
+
+        let mut most_agents = 0;
+        for _ in 0..HEADLESS_TICKS {
+            simulation.tick();
+            most_agents = most_agents.max(simulation.agent_count());
+        }
+
+        assert_eq!(simulation.ticks(), HEADLESS_TICKS);
+        assert!(simulation.wave() >= 2);
+        assert!(most_agents > 0);
+
+        // Nothing defends the base, agents reach it and are removed but the early waves
+        // aren't enough to destroy it
+        let health = simulation.base_health();
+        assert!(health < BASE_HEALTH);
+        assert!(health > 0);
+        assert_eq!(simulation.state(), GameState::Playing);
+    }
+
+    #[test]
+    fn rejects_unknown_goals() {
+        let archetypes = Archetypes::builtin();
+        let mut waves = WaveSchedule::builtin(&archetypes).unwrap();
+        waves.waves[0].groups[0].goals = "outpost".to_string();
+
+        match Simulation::scenario(MAP_SEED, archetypes, waves) {
+            Err(DataError::UnknownGoals { name }) => assert_eq!(name, "outpost"),
+            Err(err) => panic!("expected unknown goals, got {}", err),
+            Ok(_) => panic!("expected unknown goals"),
+        }
+    }
+
+    #[test]
+    fn defeat_stops_waves() {
+        let mut simulation = scenario();
+        simulation
+            .world
+            .run(|bases: View<Base>, mut healths: ViewMut<Health>| {
+                for (_, health) in (&bases, &mut healths).iter() {
+                    health.current = 1;
+                }
+            });
+
+        let mut defeated_at = None;
+        for _ in 0..HEADLESS_TICKS {
+            simulation.tick();
+            if defeated_at.is_none() && simulation.state() == GameState::Defeat {
+                defeated_at = Some(simulation.wave());
+            }
+        }
+
+        assert_eq!(simulation.state(), GameState::Defeat);
+        assert_eq!(Some(simulation.wave()), defeated_at);
+        assert_eq!(simulation.base_health(), 0);
+
+        // The destroyed base stays in the world
+        let bases = simulation.world.run(|bases: View<Base>| {
+            let mut count = 0;
+            for _ in bases.iter() {
+                count += 1;
+            }
+            count
+        });
+        assert_eq!(bases, 1);
+    }
+
+    #[test]
+    fn edits_cant_cut_nests_off() {
+        let simulation = flat();
+        let nest = Axial::new(5, 5);
+        let exit = Axial::new(6, 5);
+
+        // Walls on every side of the nest but one, which has a level of bricks to climb
+        simulation
+            .world
+            .run(|mut all_storages: AllStoragesViewMut| {
+                all_storages.run(|mut map: UniqueViewMut<Map>| {
+                    for neighbor in nest.to_hex().neighbors().iter() {
+                        let tile = map.terrain.get_tile_mut(*neighbor).unwrap();
+                        tile.wall_height = if neighbor.to_axial() == exit {
+                            1
+                        } else {
+                            MAX_BRICK_HEIGHT
+                        };
+                    }
+                });
+                entity_creator::create_nest(nest, 0, &mut all_storages);
+            });
+
+        let check = |edit| {
+            simulation
+                .world
+                .run(|mut all_storages: AllStoragesViewMut| {
+                    edits::check_edit(edit, &mut all_storages)
+                })
+        };
+
+        // A second level of bricks is more than grunts can climb
+        assert_eq!(
+            check(TerrainEdit::Raise(exit)),
+            Err(EditError::BlocksPath(nest))
+        );
+        assert_eq!(check(TerrainEdit::Raise(Axial::new(10, 10))), Ok(()));
+    }
+}
@@ -1,134 +1,726 @@
+use std::collections::HashMap;
+
 use crate::{
-    components::{Agent, Spawner, Transform},
+    archetypes::Archetypes,
+    components::{
+        Agent, Appearance, Base, DamageQueue, DeathEvent, Deaths, Health, Impact, Ledger,
+        Projectile, ProjectileTarget, Spawner, TargetPolicy, Tower, TowerKind, Transform,
+    },
     consts::*,
+    edits::{self, EditError, EditPreview, TerrainEdit},
     entity_creator,
-    map::{HexPathNode, HexTileData, Map},
+    map::{self, fractional_distance, hex_distance, round_fractional, FlowKey, Map, Movement},
+    occupancy::Occupancy,
+    overlays::{DebugOverlays, OverlayContext},
+    shading::Shading,
     shipyard::*,
+    simulation::{Clock, GameState},
     tetra::{
         graphics::{Camera, Color, Rectangle},
         input::{self, Key, MouseButton},
         math::{Vec2, Vec3},
         InputContext,
     },
+    waves::WaveDirector,
 };
 
 use vermarine_lib::{
-    hexmap::{Axial, FractionalAxial, HexMap},
+    hexmap::{Axial, FractionalAxial},
     rendering::{
         draw_buffer::{DrawBuffer, DrawCommand},
-        Drawables, Sprite,
+        Drawables,
     },
 };
 
-pub fn move_agents(map: UniqueView<Map>, agents: View<Agent>, mut transforms: ViewMut<Transform>) {
-    for (_, transform) in (&agents, &mut transforms).iter() {
-        if let Some(direction) = map.dijkstra.get_tile(transform.position.to_hex()) {
-            transform.position += direction.to_hex().to_axial();
+pub fn store_previous_transforms(map: UniqueView<Map>, mut transforms: ViewMut<Transform>) {
+    for transform in (&mut transforms).iter() {
+        transform.previous = transform.fractional();
+        transform.previous_height = transform.height(&map.terrain);
+    }
+}
+
+/// Keeps a flow field for every goal set and movement profile pair an agent is using and
+/// drops the rest
+pub fn update_flow_fields(mut map: UniqueViewMut<Map>, agents: View<Agent>) {
+    map.update_flows(agents.iter().filter_map(Agent::flow_key).collect());
+}
+
+/// Indexes every agent by the hex it's on, or moving onto
+pub fn update_occupancy(
+    mut occupancy: UniqueViewMut<Occupancy>,
+    agents: View<Agent>,
+    transforms: View<Transform>,
+) {
+    occupancy.clear();
+    for (entity, (agent, transform)) in (&agents, &transforms).iter().with_id() {
+        let hex = transform.next.unwrap_or(transform.position);
+        occupancy.insert(entity, hex, agent.movement == Movement::Flying);
+    }
+}
+
+/// Agents wait when every hex they could step onto is full
+pub fn move_agents(
+    map: UniqueView<Map>,
+    clock: UniqueView<Clock>,
+    mut occupancy: UniqueViewMut<Occupancy>,
+    agents: View<Agent>,
+    mut transforms: ViewMut<Transform>,
+) {
+    for (entity, (agent, transform)) in (&agents, &mut transforms).iter().with_id() {
+        let mut travel = agent.speed * clock.tick_length() as f32;
+        let flying = agent.movement == Movement::Flying;
+
+        while travel > 0. {
+            let next = match transform.next {
+                Some(next) => next,
+                None => {
+                    let free = next_steps(&map, agent, transform)
+                        .into_iter()
+                        .find(|&step| !occupancy.is_full(step, flying));
+
+                    match free {
+                        Some(next) => {
+                            occupancy.remove(entity, transform.position);
+                            occupancy.insert(entity, next, flying);
+                            transform.next = Some(next);
+                            next
+                        }
+                        None => break,
+                    }
+                }
+            };
+
+            let remaining = 1. - transform.progress;
+            if travel < remaining {
+                transform.progress += travel;
+                break;
+            }
+
+            travel -= remaining;
+            transform.position = next;
+            transform.next = None;
+            transform.progress = 0.;
+        }
+    }
+}
+
+/// Tiles an agent standing still could move onto next, all as short a way to a goal as
+/// each other and the preferred one first. Empty once it's reached a goal or can't get to one.
+fn next_steps(map: &Map, agent: &Agent, transform: &mut Transform) -> Vec<Axial> {
+    match agent.movement {
+        Movement::Ground(profile) => {
+            let key = FlowKey {
+                goals: agent.goals,
+                profile,
+            };
+            map.shortest_steps(transform.position, key)
+        }
+        Movement::Flying => {
+            let (path, goal) = match (
+                map.flight_path(transform.position, agent.goals),
+                map.nearest_goal(transform.position, agent.goals),
+            ) {
+                (Some(path), Some(goal)) if path.len() > 1 => (path, goal),
+                _ => return vec![],
+            };
+
+            // Climbs when the terrain ahead was raised but never sinks on the way
+            let altitude = map.flight_altitude(transform.position, agent.goals);
+            transform.altitude = transform.altitude.map(|current| current.max(altitude));
+
+            let mut steps = vec![path[1]];
+            let remaining = hex_distance(path[1], goal);
+            for neighbor in transform.position.to_hex().neighbors().iter() {
+                let step = neighbor.to_axial();
+                if hex_distance(step, goal) == remaining && hex_distance(step, path[1]) != 0 {
+                    steps.push(step);
+                }
+            }
+
+            steps
         }
     }
 }
 
+/// Interpolated pixel position of an entity between the previous and current tick,
+/// already raised by its height
+fn entity_draw_position(map: &Map, transform: &Transform, alpha: f32) -> Vec2<f32> {
+    let previous = map.fractional_to_pixel(transform.previous);
+    let current = map.fractional_to_pixel(transform.fractional());
+
+    let height = transform.previous_height
+        + (transform.height(&map.terrain) - transform.previous_height) * alpha;
+
+    Vec2::new(
+        previous.x + (current.x - previous.x) * alpha,
+        previous.y + (current.y - previous.y) * alpha - height * map.terrain.hex_depth_step,
+    )
+}
+
+/// Entities climbing between two heights are drawn along with the higher tile, flying ones
+/// aren't drawn with the terrain at all
+fn entity_draw_height(map: &Map, transform: &Transform) -> Option<u8> {
+    if transform.altitude.is_some() {
+        return None;
+    }
+
+    Some(transform.height(&map.terrain).ceil() as u8)
+}
+
+fn push_health_bar(
+    buffer: &mut Vec<DrawCommand>,
+    position: Vec2<f32>,
+    bar_tex: u64,
+    health: &Health,
+) {
+    let position = Vec3::new(
+        position.x - HEALTH_BAR_WIDTH / 2.,
+        position.y - HEALTH_BAR_OFFSET,
+        0.,
+    );
+
+    buffer.push(
+        DrawCommand::new(bar_tex)
+            .position(position)
+            .color(Color::rgb(0.3, 0.05, 0.05)),
+    );
+    buffer.push(
+        DrawCommand::new(bar_tex)
+            .position(position)
+            .clip(Rectangle::new(
+                0.,
+                0.,
+                (HEALTH_BAR_WIDTH * health.fraction()).ceil(),
+                3.,
+            ))
+            .color(Color::rgb(0.2, 0.85, 0.2)),
+    );
+}
+
+/// What `draw_hex_map` draws every height of the terrain and everything on it with
+pub struct DrawContext<'a> {
+    pub map: &'a Map,
+    pub shading: &'a Shading,
+    pub drawables: &'a Drawables,
+    /// How far through the current tick moving things are drawn
+    pub alpha: f32,
+}
+
 pub fn draw_entities_at_height(
+    context: &DrawContext,
     height: u8,
     buffer: &mut Vec<DrawCommand>,
-    map: &Map,
     transforms: &View<Transform>,
-    sprites: &View<Sprite>,
+    appearances: &View<Appearance>,
 ) {
-    for (transform, sprite) in (transforms, sprites).iter().filter(|(&transform, _)| {
-        let tile_height = map
-            .terrain
-            .get_tile(transform.position.to_hex())
-            .map(|hex| hex.wall_height)
-            .unwrap_or(0) as u8;
-
-        tile_height == height
-    }) {
-        let offset_y = height as f32 * map.terrain.hex_depth_step * -1.;
-
-        let mut draw_cmd = sprite.0;
-        let position = map.terrain.axial_to_pixel(transform.position);
+    for (transform, appearance) in (transforms, appearances).iter() {
+        if entity_draw_height(context.map, transform) != Some(height) {
+            continue;
+        }
+
+        let mut draw_cmd = appearance.to_sprite(context.drawables).0;
+        let position = entity_draw_position(context.map, transform, context.alpha);
         draw_cmd.position.x += position.x;
-        draw_cmd.position.y += position.y + offset_y;
+        draw_cmd.position.y += position.y;
 
-        let tint = textures::COLOR_TINT[height as usize];
+        let tint = context.shading.top(height);
         draw_cmd.color = Color::rgb(tint, tint, tint);
 
         buffer.push(draw_cmd);
     }
 }
 
-pub fn spawn_agents(mut all_storages: AllStoragesViewMut) {
-    let spawns = all_storages.run(
-        |positions: View<Transform>, mut spawners: ViewMut<Spawner>| {
-            let mut spawns = vec![];
+/// Projectiles are drawn with the terrain level they're flying over, ones above the tallest
+/// level are drawn last
+pub fn draw_projectiles_at_height(
+    context: &DrawContext,
+    height: u8,
+    buffer: &mut Vec<DrawCommand>,
+    projectiles: &View<Projectile>,
+    appearances: &View<Appearance>,
+) {
+    let (map, alpha) = (context.map, context.alpha);
+    let depth_step = map.terrain.hex_depth_step;
 
-            for (pos, spawner) in (&positions, &mut spawners).iter() {
-                spawner.counter -= 1;
+    for (projectile, appearance) in (projectiles, appearances).iter() {
+        let pixel_height =
+            projectile.previous_height + (projectile.height - projectile.previous_height) * alpha;
+        let level = ((pixel_height / depth_step).ceil().max(0.) as u8).min(map.terrain.tallest);
+        if level != height {
+            continue;
+        }
 
-                if spawner.counter == 0 {
-                    spawner.counter = spawner.period;
+        let previous = map.fractional_to_pixel(projectile.previous);
+        let current = map.fractional_to_pixel(projectile.position);
 
-                    spawns.push(pos.position);
-                }
-            }
+        let mut draw_cmd = appearance.to_sprite(context.drawables).0;
+        draw_cmd.position.x += previous.x + (current.x - previous.x) * alpha;
+        draw_cmd.position.y += previous.y + (current.y - previous.y) * alpha - pixel_height;
 
-            spawns
-        },
-    );
+        let tint = context.shading.top(level);
+        draw_cmd.color = Color::rgb(tint, tint, tint);
+
+        buffer.push(draw_cmd);
+    }
+}
+
+pub fn draw_health_bars_at_height(
+    context: &DrawContext,
+    height: u8,
+    buffer: &mut Vec<DrawCommand>,
+    transforms: &View<Transform>,
+    healths: &View<Health>,
+) {
+    let bar_tex = context.drawables.alias[textures::HEALTH_BAR];
+    for (transform, health) in (transforms, healths).iter() {
+        if entity_draw_height(context.map, transform) != Some(height) {
+            continue;
+        }
 
-    for position in spawns {
-        entity_creator::create_agent(position, &mut all_storages);
+        let position = entity_draw_position(context.map, transform, context.alpha);
+        push_health_bar(buffer, position, bar_tex, health);
     }
 }
 
-pub fn draw_agent_paths(
+/// Flying entities are drawn in their own layer on top of the terrain and everything on it
+pub fn draw_flyers(
     drawables: NonSendSync<UniqueViewMut<Drawables>>,
     mut draw_buffer: UniqueViewMut<DrawBuffer>,
     map: UniqueView<Map>,
-    agents: View<Agent>,
+    clock: UniqueView<Clock>,
     transforms: View<Transform>,
+    appearances: View<Appearance>,
+    healths: View<Health>,
 ) {
-    let arrow_sheet = drawables.alias[textures::ARROW_SHEET];
-    for (_, transform) in (&agents, &transforms).iter() {
-        if let Some(path) = map.get_path(transform.position.to_hex()) {
-            for step in path {
-                draw_arrow(&mut draw_buffer, arrow_sheet, &map, step.to_axial());
+    draw_buffer.new_command_pool(true);
+    let command_pool = draw_buffer.get_command_pool();
+
+    let bar_tex = drawables.alias[textures::HEALTH_BAR];
+    let alpha = clock.alpha();
+
+    for (transform, appearance) in (&transforms, &appearances).iter() {
+        if transform.altitude.is_none() {
+            continue;
+        }
+
+        let mut draw_cmd = appearance.to_sprite(&drawables).0;
+        let position = entity_draw_position(&map, transform, alpha);
+        draw_cmd.position.x += position.x;
+        draw_cmd.position.y += position.y;
+
+        command_pool.commands.push(draw_cmd);
+    }
+
+    let mut bar_buffer = vec![];
+    for (transform, health) in (&transforms, &healths).iter() {
+        if transform.altitude.is_some() {
+            let position = entity_draw_position(&map, transform, alpha);
+            push_health_bar(&mut bar_buffer, position, bar_tex, health);
+        }
+    }
+    command_pool.commands.extend(&bar_buffer);
+
+    draw_buffer.end_command_pool();
+}
+
+/// Agents standing on a goal damage the base and are removed
+pub fn agents_reach_base(mut all_storages: AllStoragesViewMut) {
+    let arrived = all_storages.run(
+        |map: UniqueView<Map>,
+         mut queue: UniqueViewMut<DamageQueue>,
+         agents: View<Agent>,
+         bases: View<Base>,
+         transforms: View<Transform>| {
+            let mut arrived = vec![];
+            for (entity, (agent, transform)) in (&agents, &transforms).iter().with_id() {
+                if transform.next.is_none() && map.is_goal(transform.position.to_hex(), agent.goals)
+                {
+                    for (base_entity, base) in bases.iter().with_id() {
+                        if base.goals == agent.goals {
+                            queue.push(base_entity, agent.damage);
+                        }
+                    }
+
+                    arrived.push(entity);
+                }
+            }
+
+            arrived
+        },
+    );
+
+    for &entity in arrived.iter() {
+        all_storages.delete(entity);
+    }
+}
+
+/// Towers pick a target in range according to their policy and launch a projectile at it
+pub fn fire_towers(mut all_storages: AllStoragesViewMut) {
+    let shots = all_storages.run(
+        |map: UniqueView<Map>,
+         occupancy: UniqueView<Occupancy>,
+         agents: View<Agent>,
+         healths: View<Health>,
+         transforms: View<Transform>,
+         mut towers: ViewMut<Tower>| {
+            let mut shots = vec![];
+
+            for (tower, tower_transform) in (&mut towers, &transforms).iter() {
+                tower.timer = tower.timer.saturating_sub(1);
+
+                let range = match map.terrain.get_tile(tower_transform.position.to_hex()) {
+                    Some(tile) => tower.range_at(tile),
+                    None => continue,
+                };
+
+                // Lower scores are better targets
+                let mut best: Option<(i64, EntityId, &Transform)> = None;
+                for entity in occupancy.within(tower_transform.position, range) {
+                    let (agent, health, transform) =
+                        match (&agents, &healths, &transforms).get(entity) {
+                            Ok(components) => components,
+                            // Reached the base earlier this tick
+                            Err(_) => continue,
+                        };
+                    let distance = hex_distance(tower_transform.position, transform.position);
+                    // Occupancy indexes agents by the tile they are moving into, which can be in
+                    // range while the tile they are on is not
+                    if distance as u32 > range {
+                        continue;
+                    }
+
+                    let score = match tower.policy {
+                        TargetPolicy::First => match agent.flow_key() {
+                            Some(key) => map
+                                .distance_to_goal(transform.position.to_hex(), key)
+                                .map_or(i64::MAX, |distance| distance as i64),
+                            None => map.nearest_goal(transform.position, agent.goals).map_or(
+                                i64::MAX,
+                                |goal| {
+                                    (hex_distance(transform.position, goal) as u32 * STEP_COST)
+                                        as i64
+                                },
+                            ),
+                        },
+                        TargetPolicy::Closest => distance as i64,
+                        TargetPolicy::Strongest => -(health.current as i64),
+                    };
+
+                    if best.is_none_or(|(best, _, _)| score < best) {
+                        best = Some((score, entity, transform));
+                    }
+                }
+
+                tower.target = best.map(|(_, entity, _)| entity);
+
+                if let Some((_, target, transform)) = best {
+                    if tower.timer == 0 {
+                        let (target, destination) = match tower.impact {
+                            Impact::Single => {
+                                (ProjectileTarget::Entity(target), transform.fractional())
+                            }
+                            // Splash lands on the ground the target was on
+                            Impact::Splash { .. } => (
+                                ProjectileTarget::Hex(transform.position),
+                                FractionalAxial {
+                                    q: transform.position.q as f32,
+                                    r: transform.position.r as f32,
+                                },
+                            ),
+                        };
+
+                        shots.push((
+                            tower_transform.position,
+                            target,
+                            destination,
+                            tower.damage,
+                            tower.impact,
+                        ));
+                        tower.timer = tower.cooldown;
+                    }
+                }
+            }
+
+            shots
+        },
+    );
+
+    for &(origin, target, destination, damage, impact) in shots.iter() {
+        entity_creator::create_projectile(
+            origin,
+            target,
+            destination,
+            damage,
+            impact,
+            &mut all_storages,
+        );
+    }
+}
+
+enum Flight {
+    Flying,
+    Landed,
+    /// Hit a column of terrain taller than the projectile
+    Blocked,
+}
+
+/// Moves a projectile one tick along its arc, checking the terrain under it every half hex
+/// so it can't skip over a wall in between
+fn fly_projectile(map: &Map, projectile: &mut Projectile, delta: f32) -> Flight {
+    let landing = round_fractional(projectile.destination);
+    let mut travel = projectile.speed * delta;
+
+    while travel > 0. {
+        let remaining = fractional_distance(projectile.position, projectile.destination);
+        let step = travel.min(0.5);
+
+        if step >= remaining {
+            projectile.position = projectile.destination;
+            projectile.height = projectile.destination_height;
+            projectile.travelled += remaining;
+            return Flight::Landed;
+        }
+
+        let t = step / remaining;
+        projectile.position = FractionalAxial {
+            q: projectile.position.q + (projectile.destination.q - projectile.position.q) * t,
+            r: projectile.position.r + (projectile.destination.r - projectile.position.r) * t,
+        };
+        projectile.travelled += step;
+        projectile.height = projectile.arc_height(projectile.travelled, remaining - step);
+        travel -= step;
+
+        let hex = round_fractional(projectile.position);
+        if hex_distance(hex, landing) == 0 {
+            continue;
+        }
+        if let Some(tile) = map.terrain.get_tile(hex.to_hex()) {
+            if tile.wall_height as f32 * map.terrain.hex_depth_step > projectile.height {
+                return Flight::Blocked;
             }
         }
     }
+
+    Flight::Flying
 }
 
-pub fn draw_arrow(draw_buffer: &mut DrawBuffer, arrow_sheet: u64, map: &Map, tile: Axial) {
-    let pos = map.terrain.axial_to_pixel(tile);
+fn projectile_impact(
+    projectile: &Projectile,
+    alive: &HashMap<EntityId, (FractionalAxial, f32)>,
+    queue: &mut DamageQueue,
+    agents: &View<Agent>,
+    transforms: &View<Transform>,
+) {
+    let landing = round_fractional(projectile.destination);
 
-    let (terrain_tile, flow_tile) = if let (Some(terrain_tile), Some(flow_tile)) = (
-        map.terrain.get_tile(tile.to_hex()),
-        map.dijkstra.get_tile(tile.to_hex()),
-    ) {
-        (terrain_tile, flow_tile)
-    } else {
+    match (projectile.impact, projectile.target) {
+        (Impact::Single, ProjectileTarget::Entity(target)) => {
+            if alive.contains_key(&target) {
+                queue.push(target, projectile.damage);
+            }
+        }
+        (Impact::Single, ProjectileTarget::Hex(_)) => {
+            for (entity, (_, transform)) in (agents, transforms).iter().with_id() {
+                if hex_distance(transform.position, landing) == 0 {
+                    queue.push(entity, projectile.damage);
+                    break;
+                }
+            }
+        }
+        (Impact::Splash { radius }, _) => {
+            for (entity, (_, transform)) in (agents, transforms).iter().with_id() {
+                if hex_distance(transform.position, landing) as u32 <= radius {
+                    queue.push(entity, projectile.damage);
+                }
+            }
+        }
+    }
+}
+
+/// Flies projectiles towards their targets, ones that land queue their damage and are
+/// removed along with ones blocked by the terrain
+pub fn move_projectiles(mut all_storages: AllStoragesViewMut) {
+    let finished = all_storages.run(
+        |map: UniqueView<Map>,
+         clock: UniqueView<Clock>,
+         mut queue: UniqueViewMut<DamageQueue>,
+         agents: View<Agent>,
+         healths: View<Health>,
+         transforms: View<Transform>,
+         mut projectiles: ViewMut<Projectile>| {
+            let mut alive = HashMap::new();
+            for (entity, (_, transform)) in (&healths, &transforms).iter().with_id() {
+                let height = transform.height(&map.terrain) * map.terrain.hex_depth_step;
+                alive.insert(entity, (transform.fractional(), height));
+            }
+
+            let mut finished = vec![];
+            for (entity, projectile) in (&mut projectiles).iter().with_id() {
+                projectile.previous = projectile.position;
+                projectile.previous_height = projectile.height;
+
+                if let ProjectileTarget::Entity(target) = projectile.target {
+                    if let Some(&(destination, height)) = alive.get(&target) {
+                        projectile.destination = destination;
+                        projectile.destination_height = height;
+                    }
+                }
+
+                match fly_projectile(&map, projectile, clock.tick_length() as f32) {
+                    Flight::Flying => {}
+                    Flight::Landed => {
+                        projectile_impact(projectile, &alive, &mut queue, &agents, &transforms);
+                        finished.push(entity);
+                    }
+                    Flight::Blocked => finished.push(entity),
+                }
+            }
+
+            finished
+        },
+    );
+
+    for &entity in finished.iter() {
+        all_storages.delete(entity);
+    }
+}
+
+/// Places a tower under the cursor with `T` or a mortar with `M`, `1` `2` and `3` pick how the
+/// next one targets
+pub fn place_towers(mut all_storages: AllStoragesViewMut) {
+    let placement = all_storages.run(
+        |input_ctx: UniqueView<InputContext>,
+         camera: UniqueView<Camera>,
+         map: UniqueView<Map>,
+         mut policy: UniqueViewMut<TargetPolicy>| {
+            for &(key, selected) in [
+                (Key::Num1, TargetPolicy::First),
+                (Key::Num2, TargetPolicy::Closest),
+                (Key::Num3, TargetPolicy::Strongest),
+            ]
+            .iter()
+            {
+                if input::is_key_pressed(&input_ctx, key) {
+                    *policy = selected;
+                }
+            }
+
+            let kind = if input::is_key_pressed(&input_ctx, Key::T) {
+                TowerKind::Bolt
+            } else if input::is_key_pressed(&input_ctx, Key::M) {
+                TowerKind::Mortar
+            } else {
+                return None;
+            };
+
+            map.pixel_to_hex(camera.mouse_position(&input_ctx))
+                .map(|axial| (axial, kind, *policy))
+        },
+    );
+
+    if let Some((position, kind, policy)) = placement {
+        let edit = TerrainEdit::PlaceTower(position, kind, policy);
+        if let Err(err) = edits::apply_edit(edit, &mut all_storages) {
+            eprintln!("Can't place a tower there: {}", err);
+        }
+    }
+}
+
+/// Pays out the income for this tick and for every kill during it
+pub fn collect_income(mut ledger: UniqueViewMut<Ledger>, deaths: UniqueView<Deaths>) {
+    let income = ledger.income_per_tick + ledger.income_per_kill * deaths.events.len() as u32;
+    ledger.resources = ledger.resources.saturating_add(income);
+}
+
+pub fn check_defeat(mut state: UniqueViewMut<GameState>, bases: View<Base>, healths: View<Health>) {
+    for (_, health) in (&bases, &healths).iter() {
+        if health.is_dead() {
+            *state = GameState::Defeat;
+        }
+    }
+}
+
+pub fn apply_damage(mut queue: UniqueViewMut<DamageQueue>, mut healths: ViewMut<Health>) {
+    if queue.pending.is_empty() {
         return;
-    };
+    }
 
-    draw_buffer.draw(
-        DrawCommand::new(arrow_sheet)
-            .position(Vec3::new(
-                pos.x,
-                pos.y,
-                terrain_tile.wall_height as f32 * map.terrain.hex_depth_step,
-            ))
-            .draw_iso(true)
-            .clip(match flow_tile {
-                HexPathNode::TopLeft => Rectangle::row(0., 0., 36., 36.).next().unwrap(),
-                HexPathNode::TopRight => Rectangle::row(0., 0., 36., 36.).nth(1).unwrap(),
-                HexPathNode::BottomRight => Rectangle::row(0., 0., 36., 36.).nth(2).unwrap(),
-                HexPathNode::BottomLeft => Rectangle::row(0., 0., 36., 36.).nth(3).unwrap(),
-                HexPathNode::Right => Rectangle::row(0., 0., 36., 36.).nth(4).unwrap(),
-                HexPathNode::Left => Rectangle::row(0., 0., 36., 36.).nth(5).unwrap(),
-                _ => return,
-            }),
+    for (entity, health) in (&mut healths).iter().with_id() {
+        if let Some(amount) = queue.pending.get(&entity) {
+            health.damage(*amount);
+        }
+    }
+
+    queue.pending.clear();
+}
+
+/// Deletes every agent that ran out of health and records its death, a destroyed base is
+/// left for `check_defeat`
+pub fn remove_dead(mut all_storages: AllStoragesViewMut) {
+    let dead = all_storages.run(|agents: View<Agent>, healths: View<Health>| {
+        let mut dead = vec![];
+
+        for (entity, (_, health)) in (&agents, &healths).iter().with_id() {
+            if health.is_dead() {
+                dead.push(DeathEvent { entity });
+            }
+        }
+
+        dead
+    });
+
+    for event in dead.iter() {
+        all_storages.delete(event.entity);
+    }
+
+    all_storages.run(|mut deaths: UniqueViewMut<Deaths>| deaths.events.extend(dead));
+}
+
+/// Spawns whatever the wave director orders this tick at the nests it names
+pub fn direct_waves(mut all_storages: AllStoragesViewMut) {
+    let spawns = all_storages.run(
+        |map: UniqueView<Map>,
+         archetypes: UniqueView<Archetypes>,
+         mut director: UniqueViewMut<WaveDirector>,
+         transforms: View<Transform>,
+         spawners: View<Spawner>| {
+            let mut spawns = vec![];
+
+            for order in director.tick() {
+                let goals = map
+                    .goal_set(&order.goals)
+                    .expect("waves are checked against the goal sets when the level starts");
+
+                for (transform, spawner) in (&transforms, &spawners).iter() {
+                    if spawner.id == order.nest {
+                        let archetype = archetypes.list[order.enemy].clone();
+                        spawns.push((transform.position, archetype, goals, order.scale));
+                    }
+                }
+            }
+
+            spawns
+        },
     );
+
+    for (position, archetype, goals, scale) in spawns {
+        entity_creator::create_agent(position, &archetype, goals, scale, &mut all_storages);
+    }
+}
+
+pub fn toggle_overlays(
+    input_ctx: UniqueView<InputContext>,
+    mut overlays: UniqueViewMut<DebugOverlays>,
+    mut map: UniqueViewMut<Map>,
+) {
+    overlays.toggle_pressed(&input_ctx);
+
+    // Flow fields the enabled layers draw are built straight away and kept without agents
+    let keys = overlays.flow_keys(&map);
+    for &key in keys.iter() {
+        map.require_flow(key);
+    }
+    map.kept_flows = keys;
 }
 
 pub fn move_camera(mut camera: UniqueViewMut<Camera>, input: UniqueView<InputContext>) {
@@ -154,80 +746,112 @@ pub fn move_camera(mut camera: UniqueViewMut<Camera>, input: UniqueView<InputCon
         movement.y = movement.y.floor();
         camera.position += movement;
     }
+
+    let wheel = input::get_mouse_wheel_movement(&input).y;
+    if wheel != 0 {
+        let current = ZOOM_LEVELS
+            .iter()
+            .position(|&zoom| zoom >= camera.zoom)
+            .unwrap_or(ZOOM_LEVELS.len() - 1);
+        let level = (current as i32 + wheel.signum())
+            .max(0)
+            .min(ZOOM_LEVELS.len() as i32 - 1);
+        let zoom = ZOOM_LEVELS[level as usize];
+
+        // Keep the world point under the cursor where it is on screen
+        let from_center = input::get_mouse_position(&input)
+            - Vec2::new(camera.viewport_width, camera.viewport_height) / 2.;
+        let cursor = camera.position + from_center / camera.zoom;
+        camera.position = cursor - from_center / zoom;
+        camera.zoom = zoom;
+    }
 }
 
-pub fn update_hex_map(
-    input_ctx: UniqueView<InputContext>,
+/// Q and E turn the map 60 degrees around the middle of the screen
+pub fn rotate_view(
     mut map: UniqueViewMut<Map>,
-    camera: UniqueView<Camera>,
+    mut camera: UniqueViewMut<Camera>,
+    input: UniqueView<InputContext>,
 ) {
-    let axial = if let Some(hex) = map.terrain.pixel_to_hex(camera.mouse_position(&input_ctx)) {
-        hex
+    let steps = if input::is_key_pressed(&input, Key::E) {
+        1
+    } else if input::is_key_pressed(&input, Key::Q) {
+        -1
     } else {
         return;
     };
 
-    let tile = map.terrain.get_tile_mut(axial.to_hex()).unwrap();
-
-    let mut modified = false;
-
-    if input::is_mouse_button_pressed(&input_ctx, MouseButton::Left) {
-        if tile.ground_height > tile.wall_height && tile.ground_height > 0 {
-            tile.ground_height -= 1;
-            modified = true;
-        } else if tile.wall_height > tile.ground_height && tile.wall_height > 0 {
-            tile.wall_height -= 1;
-            modified = true;
-        } else if tile.wall_height == tile.ground_height && tile.wall_height > 0 {
-            tile.wall_height -= 1;
-            tile.ground_height -= 1;
-            modified = true;
-        }
-    } else if input::is_mouse_button_pressed(&input_ctx, MouseButton::Right) {
-        if tile.ground_height > tile.wall_height {
-            tile.wall_height = tile.ground_height + 1;
-            modified = true;
-        } else if tile.wall_height >= tile.ground_height && tile.wall_height < MAX_BRICK_HEIGHT {
-            tile.wall_height += 1;
-            modified = true;
-        }
-    }
+    let center = map.pixel_to_fractional(camera.position);
+    map.rotation = map.rotation.turned(steps);
+    camera.position = map.fractional_to_pixel(center);
+}
+
+/// Tiles the camera can see, in draw order
+pub fn visible_tiles(map: &Map, camera: &Camera) -> Vec<Axial> {
+    let half_size = Vec2::new(camera.viewport_width, camera.viewport_height) / (2. * camera.zoom);
+    map::tiles_in_view(
+        map,
+        camera.position - half_size,
+        camera.position + half_size,
+    )
+}
+
+/// Left click lowers the tile under the cursor, right click raises it
+pub fn update_hex_map(mut all_storages: AllStoragesViewMut) {
+    let (hovered, edit) = all_storages.run(
+        |input_ctx: UniqueView<InputContext>, map: UniqueView<Map>, camera: UniqueView<Camera>| {
+            let hovered = map.pixel_to_hex(camera.mouse_position(&input_ctx));
+
+            let edit = hovered.and_then(|axial| {
+                if input::is_mouse_button_pressed(&input_ctx, MouseButton::Left) {
+                    Some(TerrainEdit::Lower(axial))
+                } else if input::is_mouse_button_pressed(&input_ctx, MouseButton::Right) {
+                    Some(TerrainEdit::Raise(axial))
+                } else {
+                    None
+                }
+            });
 
-    if modified {
-        let tile = map.terrain.get_tile_mut(axial.to_hex()).unwrap();
+            (hovered, edit)
+        },
+    );
 
-        let height = tile.wall_height;
-        if height > map.terrain.tallest {
-            map.terrain.tallest = height;
+    if let Some(edit) = edit {
+        match edits::apply_edit(edit, &mut all_storages) {
+            Ok(()) => {
+                all_storages.run(|mut preview: UniqueViewMut<EditPreview>| preview.invalidate())
+            }
+            Err(err) => eprintln!("Can't edit the terrain there: {}", err),
         }
+    }
 
-        let goal_hex = Axial::new(10, 5).to_hex();
-        let goals = vec![goal_hex + Axial::new(0, 1), goal_hex + Axial::new(1, 1)];
-        map.update_dijkstra(goals);
+    let recheck =
+        all_storages.run(|mut preview: UniqueViewMut<EditPreview>| preview.hover(hovered));
+    if let (true, Some(axial)) = (recheck, hovered) {
+        let check = edits::check_edit(TerrainEdit::Raise(axial), &mut all_storages);
+        let blocked = matches!(check, Err(EditError::BlocksPath(_)));
+        all_storages.run(|mut preview: UniqueViewMut<EditPreview>| preview.set_blocked(blocked));
     }
 }
 
 pub fn draw_hex_map(
-    input_ctx: UniqueView<InputContext>,
+    preview: UniqueView<EditPreview>,
     drawables: NonSendSync<UniqueViewMut<Drawables>>,
     mut draw_buffer: UniqueViewMut<DrawBuffer>,
-    mut map: UniqueViewMut<Map>,
+    map: UniqueView<Map>,
     camera: UniqueView<Camera>,
-    transforms: View<Transform>,
-    sprites: View<Sprite>,
+    clock: UniqueView<Clock>,
+    (transforms, appearances, healths, projectiles): (
+        View<Transform>,
+        View<Appearance>,
+        View<Health>,
+        View<Projectile>,
+    ),
 ) {
     draw_buffer.new_command_pool(true);
     let command_pool = draw_buffer.get_command_pool();
 
-    let mouse_pos = camera.mouse_position(&input_ctx);
-    let selected_hex = map.terrain.pixel_to_hex(mouse_pos);
-
-    let FractionalAxial { q, r } = map.terrain.pixel_to_hex_raw(camera.position, 0.);
-
-    let startq = (q - 40.0) as i32;
-    let endq = (q + 40.0) as i32;
-    let startr = (r - 20.0) as i32;
-    let endr = (r + 20.0) as i32;
+    let tiles = visible_tiles(&map, &camera);
 
     let (top_tex, wall_tex, brick_tex, brick_floor_tex) = (
         drawables.alias[textures::FLOOR],
@@ -236,6 +860,14 @@ pub fn draw_hex_map(
         drawables.alias[textures::FLOOR_BRICK],
     );
 
+    let shading = Shading::new(&map);
+    let context = DrawContext {
+        map: &map,
+        shading: &shading,
+        drawables: &drawables,
+        alpha: clock.alpha(),
+    };
+
     for height in 0..=map.terrain.tallest {
         let mut wall_buffer: Vec<DrawCommand> = Vec::with_capacity(1024);
         let mut wall_brick_buffer: Vec<DrawCommand> = Vec::with_capacity(1024);
@@ -244,83 +876,68 @@ pub fn draw_hex_map(
 
         let mut entity_buffer: Vec<DrawCommand> = Vec::with_capacity(1024);
 
-        for r in startr..=endr {
-            for q in startq..=endq {
-                let axial = Axial::new(q as i32, r as i32);
+        for &axial in tiles.iter() {
+            let (q, r) = (axial.q, axial.r);
 
-                let tile = if let Some(tile) = map.terrain.get_tile(axial.to_hex()) {
-                    tile
-                } else {
-                    continue;
-                };
+            let tile = if let Some(tile) = map.terrain.get_tile(axial.to_hex()) {
+                tile
+            } else {
+                continue;
+            };
 
-                if tile.wall_height < height {
-                    continue;
-                }
+            if tile.wall_height < height {
+                continue;
+            }
 
-                let (draw_x, draw_y) = {
-                    let offset_x = (map.terrain.hex_width / 2.0) * r as f32;
-                    let mut x = map.terrain.hex_width * q as f32;
-                    x += offset_x;
-                    (
-                        x + map.terrain.position.x,
-                        (r as f32 * map.terrain.hex_vert_step) + map.terrain.position.y,
-                    )
-                };
+            let pixel = map.axial_to_pixel(axial);
+            let (draw_x, draw_y) = (pixel.x, pixel.y);
 
-                if height <= tile.ground_height {
-                    draw_hex_walls(
-                        &map.terrain,
-                        &mut wall_buffer,
-                        draw_x,
-                        draw_y,
-                        height,
-                        wall_tex,
-                    );
-                }
-                if height > tile.ground_height && height <= tile.wall_height {
-                    draw_hex_bricks(
-                        &map.terrain,
-                        &mut wall_brick_buffer,
-                        draw_x,
-                        draw_y,
-                        height,
-                        brick_tex,
-                    );
-                }
+            if height <= tile.ground_height {
+                draw_hex_walls(&context, &mut wall_buffer, draw_x, draw_y, height, wall_tex);
+            }
+            if height > tile.ground_height && height <= tile.wall_height {
+                draw_hex_bricks(
+                    &context,
+                    &mut wall_brick_buffer,
+                    draw_x,
+                    draw_y,
+                    height,
+                    brick_tex,
+                );
+            }
 
-                let color = if let Some(axial) = selected_hex {
-                    if q == axial.q && r == axial.r {
+            let color = match preview.hovered {
+                Some(axial) if q == axial.q && r == axial.r => {
+                    if preview.blocked {
                         Color::RED
                     } else {
-                        Color::WHITE
+                        Color::rgb(1.0, 1.0, 0.6)
                     }
-                } else {
-                    Color::WHITE
-                };
-
-                if height == tile.ground_height && height == tile.wall_height {
-                    draw_hex_top(
-                        &map.terrain,
-                        &mut top_buffer,
-                        draw_x,
-                        draw_y,
-                        tile.ground_height,
-                        top_tex,
-                        color,
-                    );
-                }
-                if height == tile.wall_height && height != tile.ground_height {
-                    draw_hex_brick_top(
-                        &map.terrain,
-                        &mut top_brick_buffer,
-                        draw_x,
-                        draw_y,
-                        tile.wall_height,
-                        brick_floor_tex,
-                        color,
-                    );
                 }
+                _ => Color::WHITE,
+            };
+
+            if height == tile.ground_height && height == tile.wall_height {
+                draw_hex_top(
+                    &context,
+                    &mut top_buffer,
+                    draw_x,
+                    draw_y,
+                    tile.ground_height,
+                    top_tex,
+                    color,
+                );
+            }
+            if height == tile.wall_height && height != tile.ground_height {
+                draw_hex_brick_top(
+                    &context,
+                    &mut top_brick_buffer,
+                    draw_x,
+                    draw_y,
+                    tile.wall_height,
+                    brick_floor_tex,
+                    color,
+                );
             }
         }
 
@@ -329,53 +946,56 @@ pub fn draw_hex_map(
         command_pool.commands.extend(&top_buffer);
         command_pool.commands.extend(&top_brick_buffer);
 
-        draw_entities_at_height(height, &mut entity_buffer, &*map, &transforms, &sprites);
+        draw_entities_at_height(
+            &context,
+            height,
+            &mut entity_buffer,
+            &transforms,
+            &appearances,
+        );
+        draw_projectiles_at_height(
+            &context,
+            height,
+            &mut entity_buffer,
+            &projectiles,
+            &appearances,
+        );
+        draw_health_bars_at_height(&context, height, &mut entity_buffer, &transforms, &healths);
         command_pool.commands.extend(&entity_buffer);
     }
 
-    // Draw dots at hex centers
-    if DRAW_DOTS {
-        let marker_tex = drawables.alias[textures::MARKER];
-        for r_tile in startr..=endr {
-            for q_tile in startq..=endq {
-                let axial = Axial::new(q_tile as i32, r_tile as i32);
-                let pos = map.terrain.axial_to_pixel(axial);
-
-                let tile = if let Some(tile) = map.terrain.get_tile(axial.to_hex()) {
-                    tile
-                } else {
-                    continue;
-                };
+    draw_buffer.end_command_pool();
+}
 
-                draw_buffer.draw(
-                    DrawCommand::new(marker_tex)
-                        .position(Vec3::new(
-                            pos.x,
-                            pos.y,
-                            tile.wall_height as f32 * map.terrain.hex_depth_step,
-                        ))
-                        .draw_iso(true),
-                );
-            }
-        }
+/// Draws the enabled debug overlays on top of the terrain
+pub fn draw_overlays(
+    overlays: UniqueView<DebugOverlays>,
+    drawables: NonSendSync<UniqueViewMut<Drawables>>,
+    mut draw_buffer: UniqueViewMut<DrawBuffer>,
+    map: UniqueView<Map>,
+    camera: UniqueView<Camera>,
+    agents: View<Agent>,
+    transforms: View<Transform>,
+) {
+    if !overlays.any_enabled() {
+        return;
     }
 
-    // Draw dijkstra map
-    if DRAW_FLOW {
-        let arrow_sheet = drawables.alias[textures::ARROW_SHEET];
-        for r_tile in startr..=endr {
-            for q_tile in startq..=endq {
-                let axial = Axial::new(q_tile as i32, r_tile as i32);
-                draw_arrow(&mut draw_buffer, arrow_sheet, &map, axial);
-            }
-        }
+    draw_buffer.new_command_pool(true);
+    let context = OverlayContext {
+        map: &map,
+        drawables: &drawables,
+        tiles: visible_tiles(&map, &camera),
+        agents: (&agents, &transforms).iter().collect(),
+    };
+    for layer in overlays.enabled() {
+        (layer.draw)(&context, &mut draw_buffer);
     }
-
     draw_buffer.end_command_pool();
 }
 
 pub fn draw_hex_top(
-    map: &HexMap<HexTileData>,
+    context: &DrawContext,
     draw_buffer: &mut Vec<DrawCommand>,
     x: f32,
     y: f32,
@@ -386,8 +1006,8 @@ pub fn draw_hex_top(
     let mut draw_command = create_draw_cmd(
         x,
         y,
-        height as f32 * map.hex_depth_step,
-        textures::COLOR_TINT[height as usize],
+        height as f32 * context.map.terrain.hex_depth_step,
+        context.shading.top(height),
         texture,
     );
     if color != Color::WHITE {
@@ -397,7 +1017,7 @@ pub fn draw_hex_top(
 }
 
 pub fn draw_hex_brick_top(
-    map: &HexMap<HexTileData>,
+    context: &DrawContext,
     draw_buffer: &mut Vec<DrawCommand>,
     x: f32,
     y: f32,
@@ -408,8 +1028,8 @@ pub fn draw_hex_brick_top(
     let mut draw_command = create_draw_cmd(
         x,
         y,
-        height as f32 * map.hex_depth_step,
-        textures::COLOR_TINT[height as usize],
+        height as f32 * context.map.terrain.hex_depth_step,
+        context.shading.top(height),
         texture,
     );
     if color != Color::WHITE {
@@ -419,37 +1039,39 @@ pub fn draw_hex_brick_top(
 }
 
 pub fn draw_hex_walls(
-    map: &HexMap<HexTileData>,
+    context: &DrawContext,
     draw_buffer: &mut Vec<DrawCommand>,
     x: f32,
     y: f32,
     height: u8,
     wall_tex: u64,
 ) {
-    let start_height = height as f32 * map.hex_depth_step - map.wall_vert_offset;
+    let terrain = &context.map.terrain;
+    let start_height = height as f32 * terrain.hex_depth_step - terrain.wall_vert_offset;
     draw_buffer.push(create_draw_cmd(
         x,
         y,
         start_height,
-        textures::COLOR_TINT[height as usize],
+        context.shading.wall(height),
         wall_tex,
     ));
 }
 
 pub fn draw_hex_bricks(
-    map: &HexMap<HexTileData>,
+    context: &DrawContext,
     draw_buffer: &mut Vec<DrawCommand>,
     x: f32,
     y: f32,
     height: u8,
     brick_tex: u64,
 ) {
-    let start_height = height as f32 * map.hex_depth_step - map.wall_vert_step;
+    let terrain = &context.map.terrain;
+    let start_height = height as f32 * terrain.hex_depth_step - terrain.wall_vert_step;
     draw_buffer.push(create_draw_cmd(
         x,
         y,
         start_height,
-        textures::COLOR_TINT[height as usize],
+        context.shading.wall(height),
         brick_tex,
     ));
 }
@@ -0,0 +1,384 @@
+use std::{fs, path::Path};
+
+use crate::{
+    archetypes::Archetypes,
+    consts::*,
+    data::{self, parse, DataError, Fields},
+    map::Map,
+};
+
+/// Built in copy of the wave file, used when it can't be read
+const BUILTIN_WAVES: &str = include_str!("../../data/waves.txt");
+
+/// How much tougher agents get with every wave
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScalingCurve {
+    Constant,
+    /// Health grows by this fraction of the base health every wave
+    Linear(f32),
+    /// Health is multiplied by this every wave
+    Exponential(f32),
+}
+
+impl ScalingCurve {
+    /// Health multiplier for the wave with index `wave`, starting from 0
+    pub fn multiplier(&self, wave: u32) -> f32 {
+        match *self {
+            ScalingCurve::Constant => 1.,
+            ScalingCurve::Linear(step) => 1. + step * wave as f32,
+            ScalingCurve::Exponential(factor) => factor.powi(wave as i32),
+        }
+    }
+}
+
+/// `count` agents spawned one every `interval` ticks from the nest with id `nest`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpawnGroup {
+    pub nest: u32,
+    /// Index into `Archetypes::list`
+    pub enemy: usize,
+    /// Name of the goal set the agents head for
+    pub goals: String,
+    pub count: u32,
+    pub interval: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wave {
+    /// Ticks between the previous wave finishing spawning and this one starting
+    pub delay: u32,
+    pub groups: Vec<SpawnGroup>,
+}
+
+/// The waves of a level, played in order and repeated once the last one has spawned
+#[derive(Clone, Debug, PartialEq)]
+pub struct WaveSchedule {
+    pub scaling: ScalingCurve,
+    pub waves: Vec<Wave>,
+}
+
+impl WaveSchedule {
+    pub fn builtin(archetypes: &Archetypes) -> Result<WaveSchedule, DataError> {
+        WaveSchedule::from_text(BUILTIN_WAVES, archetypes)
+    }
+
+    pub fn load_file<P: AsRef<Path>>(
+        path: P,
+        archetypes: &Archetypes,
+    ) -> Result<WaveSchedule, DataError> {
+        WaveSchedule::from_text(&fs::read_to_string(path)?, archetypes)
+    }
+
+    /// Parses the format described at the top of `data/waves.txt`, enemies are looked up by
+    /// name in `archetypes`
+    pub fn from_text(text: &str, archetypes: &Archetypes) -> Result<WaveSchedule, DataError> {
+        let mut schedule = WaveSchedule {
+            scaling: ScalingCurve::Constant,
+            waves: vec![],
+        };
+
+        for (line, contents) in data::lines(text) {
+            let mut words = contents.split_whitespace();
+            match words.next() {
+                Some("scaling") => {
+                    schedule.scaling = match words.next() {
+                        Some("constant") => ScalingCurve::Constant,
+                        Some("linear") => ScalingCurve::Linear(parse(words.next(), line)?),
+                        Some("exponential") => {
+                            ScalingCurve::Exponential(parse(words.next(), line)?)
+                        }
+                        _ => return Err(DataError::Malformed { line }),
+                    };
+                }
+                Some("wave") => {
+                    let fields = Fields::parse(words, line)?;
+                    schedule.waves.push(Wave {
+                        delay: fields.value("delay")?,
+                        groups: vec![],
+                    });
+                }
+                Some("group") => {
+                    let fields = Fields::parse(words, line)?;
+
+                    let name = fields.get("enemy")?;
+                    let enemy = archetypes
+                        .find(name)
+                        .ok_or_else(|| DataError::UnknownEnemy {
+                            line,
+                            name: name.to_string(),
+                        })?;
+
+                    let group = SpawnGroup {
+                        nest: fields.value("nest")?,
+                        enemy,
+                        goals: fields
+                            .optional("goals")
+                            .unwrap_or(DEFAULT_GOALS)
+                            .to_string(),
+                        count: fields.value("count")?,
+                        interval: fields.value("interval")?,
+                    };
+
+                    schedule
+                        .waves
+                        .last_mut()
+                        .ok_or(DataError::GroupOutsideWave { line })?
+                        .groups
+                        .push(group);
+                }
+                _ => return Err(DataError::Malformed { line }),
+            }
+        }
+
+        if schedule.waves.is_empty() {
+            return Err(DataError::NoWaves);
+        }
+
+        Ok(schedule)
+    }
+
+    /// Checks every group heads for a goal set `map` has, goal names aren't known until the
+    /// level is built
+    pub fn check_goals(&self, map: &Map) -> Result<(), DataError> {
+        let groups = self.waves.iter().flat_map(|wave| wave.groups.iter());
+        for group in groups {
+            if map.goal_set(&group.goals).is_none() {
+                return Err(DataError::UnknownGoals {
+                    name: group.goals.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct ActiveGroup {
+    group: SpawnGroup,
+    spawned: u32,
+    /// Ticks until the next agent of the group spawns
+    timer: u32,
+}
+
+/// An agent the director wants spawned this tick
+#[derive(Clone, Debug)]
+pub struct SpawnOrder {
+    pub nest: u32,
+    /// Index into `Archetypes::list`
+    pub enemy: usize,
+    pub goals: String,
+    /// Multiplier applied to the enemy's health
+    pub scale: f32,
+}
+
+/// Plays a `WaveSchedule`, deciding every tick which nests spawn what
+pub struct WaveDirector {
+    pub schedule: WaveSchedule,
+    /// Number of waves started so far
+    started: u32,
+    /// Ticks until the next wave starts, only counts down once the current wave is spawned
+    timer: u32,
+    active: Vec<ActiveGroup>,
+}
+
+impl WaveDirector {
+    /// A schedule without waves never spawns anything
+    pub fn new(schedule: WaveSchedule) -> Self {
+        let timer = schedule.waves.first().map_or(0, |wave| wave.delay);
+
+        WaveDirector {
+            schedule,
+            started: 0,
+            timer,
+            active: vec![],
+        }
+    }
+
+    /// Number of the wave being spawned or last spawned, counting from 1. 0 before the
+    /// first wave.
+    pub fn current_wave(&self) -> u32 {
+        self.started
+    }
+
+    /// `None` while the current wave is still spawning or when there are no waves
+    pub fn ticks_to_next_wave(&self) -> Option<u32> {
+        if self.active.is_empty() && !self.schedule.waves.is_empty() {
+            Some(self.timer)
+        } else {
+            None
+        }
+    }
+
+    fn next_wave(&self) -> &Wave {
+        &self.schedule.waves[self.started as usize % self.schedule.waves.len()]
+    }
+
+    pub fn tick(&mut self) -> Vec<SpawnOrder> {
+        if self.schedule.waves.is_empty() {
+            return vec![];
+        }
+
+        if self.active.is_empty() {
+            self.timer = self.timer.saturating_sub(1);
+
+            if self.timer == 0 {
+                self.active = self
+                    .next_wave()
+                    .groups
+                    .iter()
+                    .filter(|group| group.count > 0)
+                    .cloned()
+                    .map(|group| ActiveGroup {
+                        group,
+                        spawned: 0,
+                        timer: 0,
+                    })
+                    .collect();
+                self.started += 1;
+
+                if self.active.is_empty() {
+                    self.timer = self.next_wave().delay;
+                }
+            }
+        }
+
+        let scale = self
+            .schedule
+            .scaling
+            .multiplier(self.started.saturating_sub(1));
+
+        let mut orders = vec![];
+        for active in self.active.iter_mut() {
+            active.timer = active.timer.saturating_sub(1);
+
+            if active.timer == 0 {
+                orders.push(SpawnOrder {
+                    nest: active.group.nest,
+                    enemy: active.group.enemy,
+                    goals: active.group.goals.clone(),
+                    scale,
+                });
+                active.spawned += 1;
+                active.timer = active.group.interval;
+            }
+        }
+
+        let was_spawning = !self.active.is_empty();
+        self.active
+            .retain(|active| active.spawned < active.group.count);
+        if was_spawning && self.active.is_empty() {
+            self.timer = self.next_wave().delay;
+        }
+
+        orders
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(nest: u32, count: u32, interval: u32) -> SpawnGroup {
+        SpawnGroup {
+            nest,
+            enemy: 0,
+            goals: DEFAULT_GOALS.to_string(),
+            count,
+            interval,
+        }
+    }
+
+    fn schedule() -> WaveSchedule {
+        WaveSchedule {
+            scaling: ScalingCurve::Linear(0.5),
+            waves: vec![
+                Wave {
+                    delay: 3,
+                    groups: vec![group(0, 2, 2)],
+                },
+                Wave {
+                    delay: 2,
+                    groups: vec![group(1, 1, 5), group(2, 0, 1)],
+                },
+            ],
+        }
+    }
+
+    /// Nest and scale of every order of the next tick
+    fn tick(director: &mut WaveDirector) -> Vec<(u32, f32)> {
+        director
+            .tick()
+            .into_iter()
+            .map(|order| (order.nest, order.scale))
+            .collect()
+    }
+
+    #[test]
+    fn plays_waves_in_order() {
+        let mut director = WaveDirector::new(schedule());
+        assert_eq!(director.current_wave(), 0);
+        assert_eq!(director.ticks_to_next_wave(), Some(3));
+
+        assert_eq!(tick(&mut director), vec![]);
+        assert_eq!(director.ticks_to_next_wave(), Some(2));
+        assert_eq!(tick(&mut director), vec![]);
+        assert_eq!(director.ticks_to_next_wave(), Some(1));
+
+        // The first agent of a group spawns as soon as its wave starts
+        assert_eq!(tick(&mut director), vec![(0, 1.)]);
+        assert_eq!(director.current_wave(), 1);
+        assert_eq!(director.ticks_to_next_wave(), None);
+
+        assert_eq!(tick(&mut director), vec![]);
+        assert_eq!(tick(&mut director), vec![(0, 1.)]);
+        assert_eq!(director.ticks_to_next_wave(), Some(2));
+
+        assert_eq!(tick(&mut director), vec![]);
+        assert_eq!(director.ticks_to_next_wave(), Some(1));
+
+        // Empty groups are skipped and the single agent finishes the wave straight away
+        assert_eq!(tick(&mut director), vec![(1, 1.5)]);
+        assert_eq!(director.current_wave(), 2);
+        assert_eq!(director.ticks_to_next_wave(), Some(3));
+    }
+
+    #[test]
+    fn repeats_with_scaling() {
+        let mut director = WaveDirector::new(schedule());
+        let orders: Vec<_> = (0..10).flat_map(|_| tick(&mut director)).collect();
+
+        assert_eq!(orders, vec![(0, 1.), (0, 1.), (1, 1.5), (0, 2.)]);
+        assert_eq!(director.current_wave(), 3);
+    }
+
+    #[test]
+    fn unknown_goals() {
+        let mut map = Map::from_chunks(vec![]);
+        map.set_goals(DEFAULT_GOALS, vec![]);
+        assert!(schedule().check_goals(&map).is_ok());
+
+        let mut schedule = schedule();
+        schedule.waves[1].groups[1].goals = "outpost".to_string();
+        match schedule.check_goals(&map) {
+            Err(DataError::UnknownGoals { name }) => assert_eq!(name, "outpost"),
+            other => panic!("expected unknown goals, got {:?}", other),
+        }
+
+        map.set_goals("outpost", vec![]);
+        assert!(schedule.check_goals(&map).is_ok());
+    }
+
+    #[test]
+    fn empty_schedule_spawns_nothing() {
+        let mut director = WaveDirector::new(WaveSchedule {
+            scaling: ScalingCurve::Constant,
+            waves: vec![],
+        });
+
+        for _ in 0..10 {
+            assert!(director.tick().is_empty());
+        }
+        assert_eq!(director.current_wave(), 0);
+        assert_eq!(director.ticks_to_next_wave(), None);
+    }
+}